@@ -1,4 +1,4 @@
-use acidalia::{wgpu, winit, Element};
+use acidalia::{wgpu, winit, Element, EngineEvent, Frame, Propagation};
 
 pub struct Canvas {}
 
@@ -13,8 +13,8 @@ impl<Data> Element<Data> for Canvas {
         &mut self,
         engine: &mut crate::Engine,
         data: &mut Data,
-        event: &winit::event::Event<()>,
-    ) {
+        event: &winit::event::Event<EngineEvent>,
+    ) -> Propagation {
         todo!()
     }
 
@@ -22,7 +22,8 @@ impl<Data> Element<Data> for Canvas {
         &'a mut self,
         engine: &mut crate::Engine,
         data: &mut Data,
-        frame: &wgpu::SurfaceTexture,
+        alpha: f32,
+        frame: &Frame,
         render_pass: &mut wgpu::RenderPass<'rp>,
     ) {
         todo!()
@@ -1,4 +1,7 @@
-use std::hash::{Hash, Hasher};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hasher;
+use std::sync::{OnceLock, RwLock};
 
 /// This is just an identification number.
 /// Usually, it will be generated by `#[derive(Nametag)]` at compile time.
@@ -9,6 +12,12 @@ pub type Tag = u128;
 /// as identifiers for shaders in your pipeline.
 pub trait Nametag {
     fn tag(self) -> Tag;
+
+    /// A readable name for the tag, to use in logs and error messages. `#[derive(Nametag)]`
+    /// names each variant `Enum::Variant`.
+    fn name(&self) -> Option<String> {
+        None
+    }
 }
 
 impl Nametag for Tag {
@@ -17,10 +26,142 @@ impl Nametag for Tag {
     }
 }
 
+/// Strings are hashed with [`stable_hash`], so the same string always gives the same tag.
 impl<'a> Nametag for &'a str {
     fn tag(self) -> Tag {
-        let mut hasher = std::collections::hash_map::DefaultHasher::new();
-        self.hash(&mut hasher);
-        hasher.finish() as Tag
+        stable_hash(self.as_bytes())
+    }
+
+    fn name(&self) -> Option<String> {
+        Some((*self).to_owned())
+    }
+}
+
+fn names() -> &'static RwLock<HashMap<Tag, String>> {
+    static NAMES: OnceLock<RwLock<HashMap<Tag, String>>> = OnceLock::new();
+    NAMES.get_or_init(Default::default)
+}
+
+/// Get the tag of `key`, and remember its name so that [`TagName`] can show it.
+pub fn register_tag(key: impl Nametag) -> Tag {
+    let name = key.name();
+    let tag = key.tag();
+    if let Some(name) = name {
+        register_name(tag, name);
+    }
+    tag
+}
+
+/// Give `tag` a name, replacing the one it had before.
+pub fn register_name(tag: Tag, name: impl Into<String>) {
+    names().write().unwrap().insert(tag, name.into());
+}
+
+/// The name registered for `tag`, if it has one.
+pub fn tag_name(tag: Tag) -> Option<String> {
+    names().read().unwrap().get(&tag).cloned()
+}
+
+/// Shows a tag by its registered name, or as a hex number if it doesn't have one.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TagName(pub Tag);
+
+impl fmt::Display for TagName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match tag_name(self.0) {
+            Some(name) => f.write_str(&name),
+            None => write!(f, "{:#034x}", self.0),
+        }
+    }
+}
+
+impl fmt::Debug for TagName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// A 128-bit FNV-1a hasher. Unlike `DefaultHasher`, its output is the same on every build
+/// and platform, so it can be used for hashes that get saved to disk.
+#[derive(Clone, Copy, Debug)]
+pub struct StableHasher(u128);
+
+impl StableHasher {
+    const OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+
+    pub const fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    /// Hash `bytes`, like [`Hasher::write`] but usable in constants.
+    pub const fn update(mut self, bytes: &[u8]) -> Self {
+        let mut i = 0;
+        while i < bytes.len() {
+            self.0 ^= bytes[i] as u128;
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+            i += 1;
+        }
+        self
+    }
+
+    /// The full 128-bit hash of everything written so far.
+    pub const fn finish128(&self) -> u128 {
+        self.0
+    }
+}
+
+/// Hash `bytes` with a [`StableHasher`]. This is a `const fn`, which lets
+/// `#[derive(Nametag)]` work out tags at compile time.
+pub const fn stable_hash(bytes: &[u8]) -> u128 {
+    StableHasher::new().update(bytes).finish128()
+}
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        *self = self.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0 as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reference values for 128-bit FNV-1a.
+    const EMPTY: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const A: u128 = 0xd228cb696f1a8caf78912b704e4a8964;
+    const FOOBAR: u128 = 0x343e1662793c64bf6f0d3597ba446f18;
+
+    #[test]
+    fn stable_hasher_matches_fnv1a() {
+        assert_eq!(StableHasher::new().finish128(), EMPTY);
+        let mut hasher = StableHasher::new();
+        hasher.write(b"foo");
+        hasher.write(b"bar");
+        assert_eq!(hasher.finish128(), FOOBAR);
+    }
+
+    #[test]
+    fn stable_hash_matches_fnv1a() {
+        const TAG: u128 = stable_hash(b"a");
+        assert_eq!(TAG, A);
+        assert_eq!(stable_hash(b"foobar"), FOOBAR);
+    }
+
+    #[test]
+    fn str_tags_are_stable() {
+        assert_eq!("".tag(), EMPTY);
+        assert_eq!("a".tag(), A);
+        assert_eq!("foobar".tag(), FOOBAR);
     }
 }
@@ -1,4 +1,4 @@
-use std::{marker::PhantomData, sync::Arc};
+use std::marker::PhantomData;
 
 use futures::task::SpawnExt;
 use iced_wgpu::{
@@ -6,16 +6,20 @@ use iced_wgpu::{
     Backend, Renderer, Settings, Viewport,
 };
 use iced_winit::{
-    conversion, futures, program,
+    conversion, futures, mouse, program,
     winit::{
         self,
         dpi::PhysicalPosition,
-        event::{Event, ModifiersState, WindowEvent},
+        event::{ElementState, Event, ModifiersState, WindowEvent},
     },
     Clipboard, Debug, Program, Size,
 };
 
-use acidalia::{graphics::ToExtent, shaders::InternalShaders, Element, Engine};
+use acidalia::{
+    graphics::ToExtent,
+    shaders::{InternalShaders, PipelineHandle},
+    Element, Engine, EngineEvent, Frame, Propagation,
+};
 
 const INDICES: &[u16] = &[0, 2, 1, 1, 2, 3];
 const NUM_INDICES: u32 = 6;
@@ -23,6 +27,12 @@ const NUM_INDICES: u32 = 6;
 /// Renders and handles events for objects implementing [`Program`].
 /// As all elements share a common data struct, a function must also be provided
 /// which will send messages to the state and read data from the state into the common struct.
+///
+/// Mouse clicks and scrolling over interactive widgets are consumed, as are key presses and
+/// typed characters while a text field has focus. iced doesn't report which events its
+/// widgets handled, so both are worked out from the cursor's
+/// [`mouse::Interaction`] as of the last update: clicking where the cursor shows a text
+/// cursor gives the UI keyboard focus, and clicking anywhere else takes it away.
 #[allow(dead_code)]
 pub struct IcedElement<
     D,
@@ -38,6 +48,8 @@ pub struct IcedElement<
     debug: Debug,
     cursor_position: PhysicalPosition<f64>,
     modifiers: ModifiersState,
+    /// Whether the last click landed on a text field, so key presses belong to the UI.
+    keyboard_focus: bool,
     staging_belt: wgpu::util::StagingBelt,
     pool: futures::executor::LocalPool,
     // Because iced doesn't accept the previous render pass,
@@ -49,7 +61,7 @@ pub struct IcedElement<
     index_buf: wgpu::Buffer,
     bind_group_layout: wgpu::BindGroupLayout,
     bind_group: wgpu::BindGroup,
-    pipeline: Arc<wgpu::RenderPipeline>,
+    pipeline: PipelineHandle<wgpu::RenderPipeline>,
 }
 
 impl<D, T: Program<Renderer = Renderer>, F: FnMut(&mut program::State<T>, &mut D)>
@@ -61,14 +73,17 @@ impl<D, T: Program<Renderer = Renderer>, F: FnMut(&mut program::State<T>, &mut D
         let mut debug = Debug::new();
         let viewport = Viewport::with_physical_size(
             Size::new(gs.get_size().width, gs.get_size().height),
-            engine.window.scale_factor(),
+            engine.scale_factor(),
         );
         let mut renderer = Renderer::new(Backend::new(
             &mut gs.device,
             Settings::default(),
             wgpu::TextureFormat::Bgra8UnormSrgb,
         ));
-        let clipboard = Clipboard::connect(&engine.window);
+        let clipboard = match &engine.window {
+            Some(window) => Clipboard::connect(window),
+            None => Clipboard::unconnected(),
+        };
         let cursor_position = PhysicalPosition::new(-1.0, -1.0);
         let modifiers = ModifiersState::default();
         let state = program::State::new(
@@ -142,6 +157,7 @@ impl<D, T: Program<Renderer = Renderer>, F: FnMut(&mut program::State<T>, &mut D
                     write_mask: wgpu::ColorWrites::ALL,
                 },
             )
+            .surface_target(0)
             .primitive(wgpu::PrimitiveState {
                 topology: wgpu::PrimitiveTopology::TriangleList,
                 strip_index_format: None,
@@ -153,7 +169,8 @@ impl<D, T: Program<Renderer = Renderer>, F: FnMut(&mut program::State<T>, &mut D
             })
             .depth_stencil(None)
             .multisample(1, !0, false)
-            .build();
+            .build()
+            .expect("The iced pipeline has an explicit layout");
 
         Self {
             state,
@@ -165,6 +182,7 @@ impl<D, T: Program<Renderer = Renderer>, F: FnMut(&mut program::State<T>, &mut D
             debug,
             cursor_position,
             modifiers,
+            keyboard_focus: false,
             staging_belt: wgpu::util::StagingBelt::new(5 * 1024),
             pool: futures::executor::LocalPool::new(),
             dest_tex,
@@ -185,8 +203,9 @@ impl<D, T: Program<Renderer = Renderer>, F: FnMut(&mut program::State<T>, &mut D
         &mut self,
         engine: &mut Engine,
         data: &mut D,
-        event: &acidalia::winit::event::Event<()>,
-    ) {
+        event: &acidalia::winit::event::Event<EngineEvent>,
+    ) -> Propagation {
+        let mut propagation = Propagation::PassThrough;
         match event {
             Event::WindowEvent { event: wev, .. } => {
                 match wev {
@@ -197,7 +216,7 @@ impl<D, T: Program<Renderer = Renderer>, F: FnMut(&mut program::State<T>, &mut D
                     WindowEvent::Resized(size) => {
                         self.viewport = Viewport::with_physical_size(
                             Size::new(size.width, size.height),
-                            engine.window.scale_factor(),
+                            engine.scale_factor(),
                         );
 
                         // This is just copied from `new`.
@@ -231,10 +250,34 @@ impl<D, T: Program<Renderer = Renderer>, F: FnMut(&mut program::State<T>, &mut D
                 }
 
                 if let Some(ev) =
-                    conversion::window_event(wev, engine.window.scale_factor(), self.modifiers)
+                    conversion::window_event(wev, engine.scale_factor(), self.modifiers)
                 {
                     self.state.queue_event(ev);
                 }
+
+                // Events are only processed by iced once the main events are cleared, so
+                // the best we can do here is check if the cursor is sitting on top of
+                // something interactive as of the last update.
+                let interaction = self.state.mouse_interaction();
+                match wev {
+                    WindowEvent::MouseInput { state, .. } => {
+                        if *state == ElementState::Pressed {
+                            self.keyboard_focus = interaction == mouse::Interaction::Text;
+                        }
+                        if interaction != mouse::Interaction::Idle {
+                            propagation = Propagation::Consumed;
+                        }
+                    }
+                    WindowEvent::MouseWheel { .. } if interaction != mouse::Interaction::Idle => {
+                        propagation = Propagation::Consumed;
+                    }
+                    WindowEvent::KeyboardInput { .. } | WindowEvent::ReceivedCharacter(_)
+                        if self.keyboard_focus =>
+                    {
+                        propagation = Propagation::Consumed;
+                    }
+                    _ => (),
+                }
             }
             Event::MainEventsCleared => {
                 if !self.state.is_queue_empty() {
@@ -249,19 +292,21 @@ impl<D, T: Program<Renderer = Renderer>, F: FnMut(&mut program::State<T>, &mut D
                         &mut self.debug,
                     );
 
-                    engine.window.request_redraw();
+                    engine.request_redraw();
                 }
             }
             _ => (),
         }
         (self.func)(&mut self.state, data);
+        propagation
     }
 
     fn render<'a: 'rp, 'rp>(
         &'a mut self,
         engine: &mut Engine,
         _data: &mut D,
-        _frame: &wgpu::SurfaceTexture,
+        _alpha: f32,
+        _frame: &Frame,
         render_pass: &mut wgpu::RenderPass<'rp>,
     ) {
         let gs = &mut engine.graphics_state;
@@ -300,7 +345,7 @@ impl<D, T: Program<Renderer = Renderer>, F: FnMut(&mut program::State<T>, &mut D
             .unwrap();
         self.pool.run_until_stalled();
 
-        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_pipeline(self.pipeline.get());
         render_pass.set_bind_group(0, &self.bind_group, &[]);
         render_pass.set_index_buffer(self.index_buf.slice(..), wgpu::IndexFormat::Uint16);
         render_pass.draw_indexed(0..NUM_INDICES, 0, 0..1);
@@ -6,7 +6,7 @@ struct Data {
     count: u32,
 }
 
-fn main() {
+fn main() -> Result<(), acidalia::Error> {
     let engine = EngineBuilder::new(|wb| wb.with_maximized(true))
         .bg_color(acidalia::wgpu::Color {
             r: 0.1,
@@ -14,7 +14,7 @@ fn main() {
             b: 0.3,
             a: 1.0,
         })
-        .build();
+        .build()?;
     let ui_el = ImguiElement::new(
         |ui, _engine, d: &mut Data| {
             imgui::Window::new("Main").build(ui, || {
@@ -30,4 +30,5 @@ fn main() {
 
     let data = Data::default();
     engine.run(screen!(ui_el), data);
+    Ok(())
 }
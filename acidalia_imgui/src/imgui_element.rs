@@ -1,8 +1,9 @@
 use acidalia::wgpu;
+use acidalia::winit::event::{Event, WindowEvent};
 use core::marker::PhantomData;
 pub use imgui::{self, *};
 
-use acidalia::{Element, Engine};
+use acidalia::{Element, Engine, EngineEvent, Frame, Propagation};
 
 /// Builds and renders an [`imgui::Ui`] constructed from a user-defined function.
 pub struct ImguiElement<Data, F: Fn(&Ui, &Engine, &mut Data)> {
@@ -12,6 +13,8 @@ pub struct ImguiElement<Data, F: Fn(&Ui, &Engine, &mut Data)> {
     _phantom: PhantomData<Data>,
     gui: imgui::Context,
     renderer: imgui_wgpu::Renderer,
+    // The format the renderer was built for, so it can be rebuilt if the surface changes.
+    texture_format: wgpu::TextureFormat,
     platform: imgui_winit_support::WinitPlatform,
     last_cursor: Option<Option<imgui::MouseCursor>>,
 }
@@ -23,11 +26,17 @@ impl<Data, F: Fn(&Ui, &Engine, &mut Data)> ImguiElement<Data, F> {
         let gs = &engine.graphics_state;
         let mut gui = imgui::Context::create();
         let mut platform = imgui_winit_support::WinitPlatform::init(&mut gui);
-        platform.attach_window(
-            gui.io_mut(),
-            &engine.window,
-            imgui_winit_support::HiDpiMode::Default,
-        );
+        match &engine.window {
+            Some(window) => platform.attach_window(
+                gui.io_mut(),
+                window,
+                imgui_winit_support::HiDpiMode::Default,
+            ),
+            None => {
+                let size = gs.get_size();
+                gui.io_mut().display_size = [size.width as f32, size.height as f32];
+            }
+        }
 
         gui.set_ini_filename(None);
 
@@ -44,21 +53,32 @@ impl<Data, F: Fn(&Ui, &Engine, &mut Data)> ImguiElement<Data, F> {
             }),
         }]);
 
-        let renderer_conf = imgui_wgpu::RendererConfig {
-            texture_format: gs.swapchain_descriptor.format,
-            ..Default::default()
-        };
-        let renderer = imgui_wgpu::Renderer::new(&mut gui, &gs.device, &gs.queue, renderer_conf);
+        let texture_format = gs.swapchain_descriptor.format;
+        let renderer = Self::create_renderer(&mut gui, engine, texture_format);
 
         Self {
             func,
             _phantom: PhantomData::default(),
             gui,
             renderer,
+            texture_format,
             platform,
             last_cursor: None,
         }
     }
+
+    fn create_renderer(
+        gui: &mut imgui::Context,
+        engine: &Engine,
+        texture_format: wgpu::TextureFormat,
+    ) -> imgui_wgpu::Renderer {
+        let gs = &engine.graphics_state;
+        let renderer_conf = imgui_wgpu::RendererConfig {
+            texture_format,
+            ..Default::default()
+        };
+        imgui_wgpu::Renderer::new(gui, &gs.device, &gs.queue, renderer_conf)
+    }
 }
 
 impl<Data, F: Fn(&Ui, &Engine, &mut Data)> Element<Data> for ImguiElement<Data, F> {
@@ -66,30 +86,67 @@ impl<Data, F: Fn(&Ui, &Engine, &mut Data)> Element<Data> for ImguiElement<Data,
         &mut self,
         engine: &mut Engine,
         _data: &mut Data,
-        event: &acidalia::winit::event::Event<()>,
-    ) {
-        self.platform
-            .handle_event(self.gui.io_mut(), &engine.window, event);
+        event: &Event<EngineEvent>,
+    ) -> Propagation {
+        // Without a window, there's nothing for the platform to translate events against.
+        if let Some(window) = &engine.window {
+            self.platform.handle_event(self.gui.io_mut(), window, event);
+        }
+
+        // Swallow input that imgui wants for itself, so that the elements underneath
+        // don't react to clicks and key presses aimed at the UI.
+        let io = self.gui.io();
+        if let Event::WindowEvent { event, .. } = event {
+            let captured = match event {
+                WindowEvent::CursorMoved { .. }
+                | WindowEvent::MouseInput { .. }
+                | WindowEvent::MouseWheel { .. } => io.want_capture_mouse,
+                WindowEvent::KeyboardInput { .. } | WindowEvent::ReceivedCharacter(_) => {
+                    io.want_capture_keyboard
+                }
+                _ => false,
+            };
+            if captured {
+                return Propagation::Consumed;
+            }
+        }
+        Propagation::PassThrough
     }
 
     fn render<'a: 'rp, 'rp>(
         &'a mut self,
         engine: &mut Engine,
         data: &mut Data,
-        _frame: &wgpu::SurfaceTexture,
+        _alpha: f32,
+        _frame: &Frame,
         render_pass: &mut wgpu::RenderPass<'rp>,
     ) {
+        let format = engine.graphics_state.swapchain_descriptor.format;
+        if format != self.texture_format {
+            self.renderer = Self::create_renderer(&mut self.gui, engine, format);
+            self.texture_format = format;
+        }
+
         let gs = &engine.graphics_state;
-        self.platform
-            .prepare_frame(self.gui.io_mut(), &engine.window)
-            .expect("Failed to prepare frame");
+        match &engine.window {
+            Some(window) => self
+                .platform
+                .prepare_frame(self.gui.io_mut(), window)
+                .expect("Failed to prepare frame"),
+            None => {
+                let size = gs.get_size();
+                self.gui.io_mut().display_size = [size.width as f32, size.height as f32];
+            }
+        }
 
         let ui = self.gui.frame();
         (self.func)(&ui, engine, data);
 
         if self.last_cursor != Some(ui.mouse_cursor()) {
             self.last_cursor = Some(ui.mouse_cursor());
-            self.platform.prepare_render(&ui, &engine.window);
+            if let Some(window) = &engine.window {
+                self.platform.prepare_render(&ui, window);
+            }
         }
 
         self.renderer
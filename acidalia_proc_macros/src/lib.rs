@@ -1,36 +1,194 @@
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{self, DeriveInput, Ident};
-use uuid::Uuid;
+use syn::{self, DeriveInput, Ident, Lit, LitStr, Member, Meta, NestedMeta};
 
 /// Allows an enum with variants to be used as a [`Nametag`][acidalia_core::Nametag].
-#[proc_macro_derive(Nametag)]
+///
+/// Each variant's tag is the hash of `module::path::Enum::Variant`, computed at compile time
+/// the same way as the tag of an `&str`, so it stays the same between builds as long as the
+/// enum isn't moved or renamed. A variant can be given a name of its own with
+/// `#[nametag("name")]`, in which case its tag matches `"name".tag()`. Either way, the
+/// variant's [`name`](acidalia_core::Nametag::name) is `Enum::Variant`.
+///
+/// Like `#[derive(Vertex)]`, the generated code goes through the `acidalia` crate.
+#[proc_macro_derive(Nametag, attributes(nametag))]
 pub fn nametag_derive(input: TokenStream) -> TokenStream {
     let ast: DeriveInput = syn::parse(input).unwrap();
-    let ident = ast.ident.clone();
-
-    if let syn::Data::Enum(en) = ast.data {
-        let mut idents: Vec<Ident> = vec![];
-        let mut vals: Vec<u128> = vec![];
-        for variant in &en.variants {
-            idents.push(variant.ident.clone());
-            vals.push(Uuid::new_v4().as_u128());
+    match nametag_impl(&ast) {
+        Ok(out) => out.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn nametag_impl(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let ident = &ast.ident;
+    let en = match &ast.data {
+        syn::Data::Enum(en) => en,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                ident,
+                "Nametag can only be derived for enums",
+            ))
         }
+    };
 
-        let out = quote! {
-            impl Nametag for #ident {
-                fn tag(self) -> u128 {
-                    match self {
-                        #(Self::#idents =>  { #vals }),*
-                    }
+    let mut idents: Vec<&Ident> = vec![];
+    let mut keys: Vec<proc_macro2::TokenStream> = vec![];
+    let mut explicit: Vec<(String, &Ident)> = vec![];
+    let mut names: Vec<String> = vec![];
+    for variant in &en.variants {
+        let var = &variant.ident;
+        let key = match variant.attrs.iter().find(|a| a.path.is_ident("nametag")) {
+            Some(attr) => {
+                let lit = attr.parse_args::<LitStr>()?;
+                // Generated names all differ from each other, so only the explicit ones
+                // can clash.
+                if let Some((_, other)) = explicit.iter().find(|(name, _)| *name == lit.value()) {
+                    return Err(syn::Error::new_spanned(
+                        variant,
+                        format!("`{}` has the same tag as `{}`", var, other),
+                    ));
                 }
+                explicit.push((lit.value(), var));
+                quote! { #lit }
             }
+            None => quote! {
+                ::core::concat!(
+                    ::core::module_path!(),
+                    "::",
+                    ::core::stringify!(#ident),
+                    "::",
+                    ::core::stringify!(#var)
+                )
+            },
         };
+        idents.push(var);
+        keys.push(key);
+        names.push(format!("{}::{}", ident, var));
+    }
+
+    Ok(quote! {
+        impl ::acidalia::Nametag for #ident {
+            fn tag(self) -> u128 {
+                match self {
+                    #(Self::#idents => {
+                        const TAG: u128 = ::acidalia::stable_hash(#keys.as_bytes());
+                        TAG
+                    }),*
+                }
+            }
 
-        return out.into();
+            fn name(&self) -> Option<String> {
+                let name = match self {
+                    #(Self::#idents => #names),*
+                };
+                Some(name.to_owned())
+            }
+        }
+    })
+}
+
+/// Implements [`Vertex`](../acidalia/trait.Vertex.html) for a `#[repr(C)]` struct. Each field
+/// is given the next shader location, starting from 0, and a format based on its type.
+/// Either can be overridden with `#[vertex(location = 3, format = "Unorm8x4")]`.
+#[proc_macro_derive(Vertex, attributes(vertex))]
+pub fn vertex_derive(input: TokenStream) -> TokenStream {
+    let ast: DeriveInput = syn::parse(input).unwrap();
+    match vertex_impl(&ast) {
+        Ok(out) => out.into(),
+        Err(e) => e.to_compile_error().into(),
     }
+}
 
-    return TokenStream::new();
+fn vertex_impl(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let ident = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    // Without a fixed layout, the offsets could change out from under the shaders.
+    let repr_c = ast.attrs.iter().any(|attr| {
+        attr.path.is_ident("repr")
+            && matches!(attr.parse_meta(), Ok(Meta::List(list)) if list.nested.iter().any(|n| {
+                matches!(n, NestedMeta::Meta(Meta::Path(path)) if path.is_ident("C"))
+            }))
+    });
+    if !repr_c {
+        return Err(syn::Error::new_spanned(
+            ident,
+            "`Vertex` can only be derived for `#[repr(C)]` structs",
+        ));
+    }
+
+    let fields = match &ast.data {
+        syn::Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                ident,
+                "`Vertex` can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut attributes = vec![];
+    let mut next_location = 0u32;
+    for (i, field) in fields.iter().enumerate() {
+        let member = match &field.ident {
+            Some(name) => Member::Named(name.clone()),
+            None => Member::Unnamed(i.into()),
+        };
+        let ty = &field.ty;
+        let mut location = next_location;
+        let mut format = quote! { <#ty as ::acidalia::VertexAttributeFormat>::FORMAT };
+        for attr in field.attrs.iter().filter(|a| a.path.is_ident("vertex")) {
+            let list = match attr.parse_meta()? {
+                Meta::List(list) => list,
+                meta => return Err(syn::Error::new_spanned(meta, "expected `#[vertex(...)]`")),
+            };
+            for nested in list.nested.iter() {
+                match nested {
+                    NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("location") => {
+                        match &nv.lit {
+                            Lit::Int(lit) => location = lit.base10_parse()?,
+                            lit => return Err(syn::Error::new_spanned(lit, "expected an integer")),
+                        }
+                    }
+                    NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("format") => {
+                        match &nv.lit {
+                            Lit::Str(lit) => {
+                                let name: Ident = lit.parse()?;
+                                format = quote! { ::acidalia::wgpu::VertexFormat::#name };
+                            }
+                            lit => return Err(syn::Error::new_spanned(lit, "expected a string")),
+                        }
+                    }
+                    nested => {
+                        return Err(syn::Error::new_spanned(
+                            nested,
+                            "expected `location = ...` or `format = \"...\"`",
+                        ))
+                    }
+                }
+            }
+        }
+        next_location = location + 1;
+        attributes.push(quote! {
+            ::acidalia::wgpu::VertexAttribute {
+                format: #format,
+                offset: ::core::mem::offset_of!(Self, #member) as ::acidalia::wgpu::BufferAddress,
+                shader_location: #location,
+            }
+        });
+    }
+
+    Ok(quote! {
+        impl #impl_generics ::acidalia::Vertex for #ident #ty_generics #where_clause {
+            fn layout() -> ::acidalia::VertexLayout {
+                ::acidalia::VertexLayout::new(
+                    ::core::mem::size_of::<Self>() as ::acidalia::wgpu::BufferAddress,
+                    vec![#(#attributes),*],
+                )
+            }
+        }
+    })
 }
 
 // #[proc_macro_derive(FnAlias)]
@@ -0,0 +1,242 @@
+use std::path::{Path, PathBuf};
+
+use image::{ColorType, ImageError};
+
+/// The environment variable that, when set, makes [`Capture::assert_matches_golden`]
+/// overwrite the golden image instead of comparing against it.
+pub const UPDATE_GOLDEN_VAR: &str = "ACIDALIA_UPDATE_GOLDEN";
+
+/// An image read back from the GPU, stored as tightly packed 8-bit RGBA.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capture {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+/// The result of comparing two [`Capture`]s with [`Capture::compare`].
+pub struct CaptureDiff {
+    /// How many pixels had a channel differ by more than the tolerance.
+    pub mismatched: usize,
+    /// The largest difference seen in any channel.
+    pub max_difference: u8,
+    /// A visualization of the differences. Mismatched pixels are drawn in red on top of a
+    /// faded copy of the image.
+    pub image: Capture,
+}
+
+impl Capture {
+    /// Save the capture as a PNG file.
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<(), ImageError> {
+        image::save_buffer(path, &self.data, self.width, self.height, ColorType::Rgba8)
+    }
+
+    /// Load a capture from an image file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ImageError> {
+        let img = image::open(path)?.into_rgba8();
+        Ok(Self {
+            width: img.width(),
+            height: img.height(),
+            data: img.into_raw(),
+        })
+    }
+
+    /// Compare this capture against an `expected` one, counting any pixel with a channel that
+    /// differs by more than `tolerance` as a mismatch.
+    ///
+    /// # Panics
+    /// If the two captures are not the same size.
+    pub fn compare(&self, expected: &Capture, tolerance: u8) -> CaptureDiff {
+        assert_eq!(
+            (self.width, self.height),
+            (expected.width, expected.height),
+            "Captures have different sizes"
+        );
+        let mut mismatched = 0;
+        let mut max_difference = 0;
+        let mut data = Vec::with_capacity(self.data.len());
+        for (actual, expected) in self.data.chunks(4).zip(expected.data.chunks(4)) {
+            let difference = actual
+                .iter()
+                .zip(expected)
+                .map(|(a, e)| a.abs_diff(*e))
+                .max()
+                .unwrap_or(0);
+            max_difference = max_difference.max(difference);
+            if difference > tolerance {
+                mismatched += 1;
+                data.extend_from_slice(&[255, 0, 0, 255]);
+            } else {
+                let luma = (actual[0] as u32 + actual[1] as u32 + actual[2] as u32) / 3;
+                let faded = (luma / 4) as u8;
+                data.extend_from_slice(&[faded, faded, faded, 255]);
+            }
+        }
+        CaptureDiff {
+            mismatched,
+            max_difference,
+            image: Capture {
+                width: self.width,
+                height: self.height,
+                data,
+            },
+        }
+    }
+
+    /// Assert that this capture matches the golden image stored at `path`, allowing each
+    /// channel to be off by up to `tolerance`.
+    ///
+    /// On failure, the capture is written next to the golden image as `<name>.actual.png`,
+    /// along with a diff image as `<name>.diff.png`. If the [`UPDATE_GOLDEN_VAR`] environment
+    /// variable is set, the golden image is overwritten with this capture instead.
+    pub fn assert_matches_golden(&self, path: impl AsRef<Path>, tolerance: u8) {
+        let path = path.as_ref();
+        if std::env::var_os(UPDATE_GOLDEN_VAR).is_some() {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).expect("Unable to create golden image directory");
+            }
+            self.save_png(path).expect("Unable to write golden image");
+            return;
+        }
+
+        let actual_path = sibling(path, "actual");
+        let expected = match Capture::load(path) {
+            Ok(expected) => expected,
+            Err(e) => {
+                self.save_png(&actual_path)
+                    .expect("Unable to write captured image");
+                panic!(
+                    "Unable to load golden image {}: {}. The capture was saved to {}, rerun \
+                     with {} set to accept it.",
+                    path.display(),
+                    e,
+                    actual_path.display(),
+                    UPDATE_GOLDEN_VAR
+                );
+            }
+        };
+
+        if (self.width, self.height) != (expected.width, expected.height) {
+            self.save_png(&actual_path)
+                .expect("Unable to write captured image");
+            panic!(
+                "Capture is {}x{}, but golden image {} is {}x{}. The capture was saved to {}.",
+                self.width,
+                self.height,
+                path.display(),
+                expected.width,
+                expected.height,
+                actual_path.display()
+            );
+        }
+
+        let diff = self.compare(&expected, tolerance);
+        if diff.mismatched > 0 {
+            let diff_path = sibling(path, "diff");
+            self.save_png(&actual_path)
+                .expect("Unable to write captured image");
+            diff.image
+                .save_png(&diff_path)
+                .expect("Unable to write diff image");
+            panic!(
+                "{} pixels differ from golden image {} (max difference {}, tolerance {}). \
+                 See {} and {}.",
+                diff.mismatched,
+                path.display(),
+                diff.max_difference,
+                tolerance,
+                actual_path.display(),
+                diff_path.display()
+            );
+        }
+    }
+}
+
+/// Turns `dir/name.png` into `dir/name.<suffix>.png`.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!("{}.{}.png", stem, suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, pixel: [u8; 4]) -> Capture {
+        Capture {
+            width,
+            height,
+            data: pixel.repeat((width * height) as usize),
+        }
+    }
+
+    #[test]
+    fn compare_tolerance_is_inclusive() {
+        let expected = solid(2, 2, [100, 100, 100, 255]);
+
+        let at = solid(2, 2, [104, 100, 100, 255]);
+        let diff = at.compare(&expected, 4);
+        assert_eq!(diff.mismatched, 0);
+        assert_eq!(diff.max_difference, 4);
+
+        let over = solid(2, 2, [100, 95, 100, 255]);
+        let diff = over.compare(&expected, 4);
+        assert_eq!(diff.mismatched, 4);
+        assert_eq!(diff.max_difference, 5);
+    }
+
+    #[test]
+    fn compare_counts_mismatched_pixels() {
+        let expected = solid(3, 1, [0, 0, 0, 255]);
+        let mut actual = expected.clone();
+        actual.data[2] = 200;
+        actual.data[11] = 0;
+        let diff = actual.compare(&expected, 10);
+        assert_eq!(diff.mismatched, 2);
+        assert_eq!(diff.max_difference, 255);
+        assert_eq!(&diff.image.data[0..4], &[255, 0, 0, 255]);
+        assert_eq!(&diff.image.data[4..8], &[0, 0, 0, 255]);
+        assert_eq!(&diff.image.data[8..12], &[255, 0, 0, 255]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Captures have different sizes")]
+    fn compare_rejects_size_mismatch() {
+        solid(2, 2, [0; 4]).compare(&solid(2, 1, [0; 4]), 0);
+    }
+
+    #[test]
+    fn golden_size_mismatch_is_reported() {
+        let dir = std::env::temp_dir().join(format!("acidalia-capture-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let golden = dir.join("golden.png");
+        solid(2, 2, [0, 0, 0, 255]).save_png(&golden).unwrap();
+
+        let result = std::panic::catch_unwind(|| {
+            solid(3, 2, [0, 0, 0, 255]).assert_matches_golden(&golden, 0)
+        });
+        let actual_exists = dir.join("golden.actual.png").is_file();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let message = result.expect_err("A size mismatch should fail");
+        let message = message.downcast_ref::<String>().unwrap();
+        assert!(message.contains("Capture is 3x2"), "{}", message);
+        assert!(message.contains("is 2x2"), "{}", message);
+        assert!(actual_exists);
+    }
+
+    #[test]
+    fn sibling_inserts_suffix() {
+        assert_eq!(
+            sibling(Path::new("golden/foo.png"), "actual"),
+            Path::new("golden/foo.actual.png")
+        );
+        assert_eq!(
+            sibling(Path::new("foo.png"), "diff"),
+            Path::new("foo.diff.png")
+        );
+    }
+}
@@ -1,123 +1,409 @@
+use std::any::Any;
+use std::cell::Cell;
+
+use crossbeam_channel::Receiver;
+
 use crate::{
     fps::FPSCounter,
     winit::{
         event::*,
         event_loop::{ControlFlow, EventLoop},
-        window::Window,
+        window::{Window, WindowId},
     },
 };
 use crate::{shaders::ShaderState, wgpu};
 
+use crate::capture::Capture;
+use crate::event::{EngineEvent, EventProxy};
 use crate::fps::TimingState;
-use crate::{graphics::GraphicsState, EngineBuilder};
+use crate::pacing::{FramePacer, RedrawMode};
+use crate::timestep::FixedTimestep;
+use crate::{
+    graphics::{Frame, GraphicsState},
+    EngineBuilder, Error,
+};
 
 /// The core engine that constructs the window and graphics states, and passes events
 /// to user-defined screens.
 pub struct Engine {
-    event_loop: Option<EventLoop<()>>,
-    pub window: Window,
+    event_loop: Option<EventLoop<EngineEvent>>,
+    /// The window being drawn to. This is `None` when running headless.
+    pub window: Option<Window>,
     pub graphics_state: GraphicsState,
     pub shader_state: ShaderState,
     pub background_color: wgpu::Color,
     pub fps: FPSCounter,
+    pub timestep: FixedTimestep,
+    pub pacer: FramePacer,
+    pub redraw_mode: RedrawMode,
+    redraw_pending: Cell<bool>,
+    proxy: EventProxy,
+    headless_events: Option<Receiver<EngineEvent>>,
+    transitions: Vec<Transition>,
+}
+
+/// A requested change to the screen stack. These are queued up by elements and applied
+/// by the engine once the current event has been dispatched.
+enum Transition {
+    Push(Box<dyn Any>),
+    Pop,
+    Replace(Box<dyn Any>),
+    Clear,
 }
 
 impl Engine {
-    /// Constructs a new `Engine`. Currently, this does not let you set parameters, but that
-    /// will be available in the future, likely through an `EngineBuilder`.
-    pub fn new(eb: EngineBuilder) -> Self {
-        let event_loop = EventLoop::new();
-        let window = eb.window_builder.build(&event_loop).unwrap();
-        let graphics_state = GraphicsState::new(&window);
-        let mut shader_state = ShaderState::new(&graphics_state);
-        shader_state.init_shaders();
-        Self {
-            event_loop: Some(event_loop),
+    /// Constructs a new `Engine` configured by `eb`. This opens the window and connects to the
+    /// graphics device, or sets up an offscreen target if [`EngineBuilder::headless`] was used.
+    ///
+    /// # Errors
+    /// - [`Error::NoAdapter`] or [`Error::RequestDevice`] if no usable graphics device matches
+    ///   the builder's graphics options.
+    /// - [`Error::WindowCreation`] if the window could not be created.
+    /// - [`Error::ShaderCompiler`] if the shader compiler could not be started.
+    /// - [`Error::Io`] if the builder set a shader cache directory that could not be created.
+    /// - [`Error::Shader`] if one of the engine's own shaders failed to compile.
+    pub fn new(eb: EngineBuilder) -> Result<Self, Error> {
+        let (event_loop, window, mut graphics_state, proxy, headless_events) = match eb.headless {
+            Some(size) => {
+                let graphics_state = GraphicsState::new_headless(size, &eb.graphics)?;
+                let (tx, rx) = crossbeam_channel::unbounded();
+                (
+                    None,
+                    None,
+                    graphics_state,
+                    EventProxy::headless(tx),
+                    Some(rx),
+                )
+            }
+            None => {
+                let event_loop = EventLoop::with_user_event();
+                let window = eb.window_builder.build(&event_loop)?;
+                let graphics_state = GraphicsState::new(&window, &eb.graphics)?;
+                let proxy = EventProxy::window(event_loop.create_proxy());
+                (Some(event_loop), Some(window), graphics_state, proxy, None)
+            }
+        };
+        let mut shader_state = ShaderState::new(&graphics_state, proxy.clone())?;
+        if let Some((dir, salt)) = &eb.shader_cache {
+            shader_state.set_cache_dir(dir, salt.as_str())?;
+        }
+        shader_state.init_shaders()?;
+        graphics_state.on_format_change(shader_state.format_listener());
+        Ok(Self {
+            event_loop,
             window,
             graphics_state,
             shader_state,
             background_color: eb.bg_color,
             fps: FPSCounter::new(),
+            timestep: FixedTimestep::new(eb.tick_rate, eb.max_catch_up),
+            pacer: FramePacer::new(eb.target_fps),
+            redraw_mode: eb.redraw_mode,
+            redraw_pending: Cell::new(true),
+            proxy,
+            headless_events,
+            transitions: vec![],
+        })
+    }
+
+    /// The window's scale factor, or `1.0` when running headless.
+    pub fn scale_factor(&self) -> f64 {
+        self.window
+            .as_ref()
+            .map(|w| w.scale_factor())
+            .unwrap_or(1.0)
+    }
+
+    /// Ask for the window to be redrawn. In [`RedrawMode::Reactive`], this is how elements
+    /// get a new frame drawn when nothing else is happening. This does nothing when running
+    /// headless.
+    pub fn request_redraw(&self) {
+        self.redraw_pending.set(true);
+    }
+
+    /// Whether a new frame should be drawn as soon as the frame limit allows.
+    fn wants_redraw(&self) -> bool {
+        match self.redraw_mode {
+            RedrawMode::Continuous => true,
+            RedrawMode::Reactive => self.redraw_pending.get(),
+        }
+    }
+
+    /// Pick how the event loop should wait for the next batch of events.
+    fn control_flow(&self) -> ControlFlow {
+        match (self.wants_redraw(), self.pacer.wake_time()) {
+            (true, Some(wake)) => ControlFlow::WaitUntil(wake),
+            (true, None) => ControlFlow::Poll,
+            (false, _) => ControlFlow::Wait,
+        }
+    }
+
+    /// Get a handle for sending [`EngineEvent`]s to the engine, which can be moved to
+    /// other threads.
+    pub fn event_proxy(&self) -> EventProxy {
+        self.proxy.clone()
+    }
+
+    /// Read back the last frame drawn. When running with a window, capturing has to be turned
+    /// on first with [`GraphicsState::set_capturing`]. See [`GraphicsState::capture_frame`].
+    pub fn capture_frame(&self) -> Option<Capture> {
+        self.graphics_state.capture_frame()
+    }
+
+    /// Push a new `Screen` on top of the current one. The new screen will receive all events
+    /// and draw calls until it is popped off or replaced.
+    ///
+    /// The data type of the screen must match the one the engine is running with.
+    pub fn push_screen<T: 'static>(&mut self, screen: impl ToScreen<T>) {
+        self.transitions
+            .push(Transition::Push(Box::new(screen.to_screen())));
+    }
+
+    /// Pop the current `Screen` off of the stack, returning control to the one beneath it.
+    /// If there is nothing left on the stack, the engine will exit.
+    pub fn pop_screen(&mut self) {
+        self.transitions.push(Transition::Pop);
+    }
+
+    /// Replace the current `Screen` with a new one.
+    ///
+    /// The data type of the screen must match the one the engine is running with.
+    pub fn replace_screen<T: 'static>(&mut self, screen: impl ToScreen<T>) {
+        self.transitions
+            .push(Transition::Replace(Box::new(screen.to_screen())));
+    }
+
+    /// Remove every `Screen` from the stack, causing the engine to exit.
+    pub fn clear_screens(&mut self) {
+        self.transitions.push(Transition::Clear);
+    }
+
+    /// Apply all of the queued screen transitions, calling the `enter` and `exit` hooks
+    /// of the affected elements.
+    fn apply_transitions<T: 'static>(&mut self, screens: &mut Vec<Screen<T>>, data: &mut T) {
+        if self.transitions.is_empty() {
+            return;
+        }
+        // Hooks may queue up even more transitions, so keep going until they settle.
+        while !self.transitions.is_empty() {
+            for transition in std::mem::take(&mut self.transitions) {
+                match transition {
+                    Transition::Push(screen) => self.enter_screen(screens, screen, data),
+                    Transition::Pop => self.exit_screen(screens, data),
+                    Transition::Replace(screen) => {
+                        self.exit_screen(screens, data);
+                        self.enter_screen(screens, screen, data);
+                    }
+                    Transition::Clear => {
+                        while !screens.is_empty() {
+                            self.exit_screen(screens, data);
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(screen) = screens.last() {
+            self.fps.set_elements(screen.len());
+        }
+    }
+
+    fn enter_screen<T: 'static>(
+        &mut self,
+        screens: &mut Vec<Screen<T>>,
+        screen: Box<dyn Any>,
+        data: &mut T,
+    ) {
+        let mut screen = *screen
+            .downcast::<Screen<T>>()
+            .expect("Screen data type does not match the one the engine is running with");
+        for element in screen.iter_mut() {
+            element.enter(self, data);
+        }
+        screens.push(screen);
+    }
+
+    fn exit_screen<T: 'static>(&mut self, screens: &mut Vec<Screen<T>>, data: &mut T) {
+        if let Some(mut screen) = screens.pop() {
+            for element in screen.iter_mut().rev() {
+                element.exit(self, data);
+            }
         }
     }
 
     /// Runs the event loop with an initial `Screen`.
     pub fn run<T: 'static>(mut self, screen: impl ToScreen<T>, mut data: T) {
-        let screen = screen.to_screen();
-        let evloop = self.event_loop.take().unwrap();
-        self.fps.set_elements(screen.len());
-        let mut screens: Vec<Screen<T>> = vec![screen];
+        let evloop = self
+            .event_loop
+            .take()
+            .expect("A headless engine must be run with `run_headless`");
+        let mut screens: Vec<Screen<T>> = vec![];
+        self.push_screen(screen);
+        self.apply_transitions(&mut screens, &mut data);
+        self.timestep.reset();
         evloop.run(move |event, _, control_flow| {
-            *control_flow = ControlFlow::Poll;
-            let last_sc = screens.last_mut();
-            if let Some(screen) = last_sc {
-                match event {
-                    Event::WindowEvent {
-                        event: WindowEvent::CloseRequested,
-                        ..
-                    } => *control_flow = ControlFlow::Exit,
-                    Event::WindowEvent {
-                        event: WindowEvent::Resized(_),
-                        ..
-                    } => {
-                        let size = self.window.inner_size();
-                        self.graphics_state.set_size(size);
-                    }
-                    Event::MainEventsCleared => self.window.request_redraw(),
-                    Event::RedrawEventsCleared => {
-                        let frame = match self.graphics_state.surface.get_current_texture() {
-                            Ok(frame) => frame,
-                            Err(e) => {
-                                eprintln!("dropped frame: {:?}", e);
-                                return;
-                            }
-                        };
-
-                        let mut encoder = self.graphics_state.device.create_command_encoder(
-                            &wgpu::CommandEncoderDescriptor { label: None },
-                        );
-
-                        let view = &frame
-                            .texture
-                            .create_view(&wgpu::TextureViewDescriptor::default());
-                        let mut render_pass =
-                            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                                label: None,
-                                color_attachments: &[wgpu::RenderPassColorAttachment {
-                                    view,
-                                    resolve_target: None,
-                                    ops: wgpu::Operations {
-                                        load: wgpu::LoadOp::Clear(self.background_color),
-                                        store: true,
-                                    },
-                                }],
-                                depth_stencil_attachment: None,
-                            });
-
-                        self.fps.start(TimingState::Draw);
-                        for element in screen.iter_mut() {
-                            element.render(&mut self, &mut data, &frame, &mut render_pass);
-                            self.fps.advance();
-                        }
-                        std::mem::drop(render_pass);
+            *control_flow = match self.handle_event(&mut screens, &mut data, &event) {
+                true => self.control_flow(),
+                false => ControlFlow::Exit,
+            };
+        });
+    }
 
-                        self.graphics_state.queue.submit(Some(encoder.finish()));
-                        frame.present();
+    /// Runs an initial `Screen` for a fixed number of `frames` without a window, then returns.
+    /// At the start of every frame, `events` is called with the frame number to produce the
+    /// synthetic events to send to the screen. Events sent through the [`EventProxy`] are
+    /// delivered right after them.
+    ///
+    /// Every frame advances the simulation by exactly one tick, so the results are the same
+    /// no matter how fast the frames are drawn.
+    pub fn run_headless<T: 'static>(
+        &mut self,
+        screen: impl ToScreen<T>,
+        data: &mut T,
+        frames: usize,
+        mut events: impl FnMut(usize) -> Vec<Event<'static, EngineEvent>>,
+    ) {
+        assert!(
+            self.graphics_state.is_headless(),
+            "Only a headless engine can be run with `run_headless`"
+        );
+        let mut screens: Vec<Screen<T>> = vec![];
+        self.push_screen(screen);
+        self.apply_transitions(&mut screens, data);
+        self.timestep.reset();
+        // The ID is only ever handed to elements, never back to winit, so a dummy is fine.
+        let window_id = unsafe { WindowId::dummy() };
+        let proxied = self.headless_events.clone().unwrap();
+        for frame in 0..frames {
+            let user_events: Vec<_> = proxied.try_iter().map(Event::UserEvent).collect();
+            let events = events(frame).into_iter().chain(user_events).chain([
+                Event::MainEventsCleared,
+                Event::RedrawRequested(window_id),
+                Event::RedrawEventsCleared,
+            ]);
+            for event in events {
+                if !self.handle_event(&mut screens, data, &event) {
+                    return;
+                }
+            }
+        }
+    }
 
-                        self.shader_state.cull();
+    /// Dispatch a single event to the top screen. Returns `false` when the engine should stop.
+    fn handle_event<T: 'static>(
+        &mut self,
+        screens: &mut Vec<Screen<T>>,
+        data: &mut T,
+        event: &Event<EngineEvent>,
+    ) -> bool {
+        let screen = match screens.last_mut() {
+            Some(screen) => screen,
+            None => return false,
+        };
+        let mut running = true;
+        match event {
+            Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                ..
+            } => running = false,
+            Event::WindowEvent {
+                event: WindowEvent::Resized(size),
+                ..
+            } => {
+                self.graphics_state.set_size(*size);
+                self.request_redraw();
+            }
+            // Input and engine events (like a shader reloading) can both change what's on
+            // screen.
+            Event::WindowEvent { .. } | Event::UserEvent(_)
+                if self.redraw_mode == RedrawMode::Reactive =>
+            {
+                self.request_redraw();
+            }
+            Event::MainEventsCleared => {
+                let steps = match self.window {
+                    Some(_) => self.timestep.advance(),
+                    None => self.timestep.advance_fixed(),
+                };
+                let dt = self.timestep.dt();
+                'ticks: for _ in 0..steps {
+                    for element in screen.iter_mut() {
+                        element.tick(self, data, dt);
+                    }
+                    // Don't keep simulating a screen that's on its way out.
+                    if !self.transitions.is_empty() {
+                        break 'ticks;
                     }
-                    _ => (),
                 }
-                self.fps.start(TimingState::Update);
-                for element in screen.iter_mut().rev() {
-                    // TODO: allow event cancelling
-                    element.update(&mut self, &mut data, &event);
-                    self.fps.advance();
+                // Headless engines draw every frame, and shouldn't be held back by the limiter.
+                if let Some(window) = &self.window {
+                    if self.wants_redraw() && self.pacer.ready() {
+                        self.redraw_pending.set(false);
+                        window.request_redraw();
+                    }
                 }
-            } else {
-                *control_flow = ControlFlow::Exit;
             }
+            Event::RedrawRequested(_) => {
+                self.draw(screen, data);
+                self.pacer.frame_drawn();
+            }
+            _ => (),
+        }
+        self.fps.start(TimingState::Update);
+        for element in screen.iter_mut().rev() {
+            let propagation = element.update(self, data, event);
+            self.fps.advance();
+            if propagation == Propagation::Consumed {
+                break;
+            }
+        }
+        self.apply_transitions(screens, data);
+        if !running {
+            // Give every screen still on the stack a chance to clean up after itself.
+            while !screens.is_empty() {
+                self.exit_screen(screens, data);
+            }
+        }
+        running && !screens.is_empty()
+    }
+
+    /// Draw a single frame of the given screen.
+    fn draw<T: 'static>(&mut self, screen: &mut Screen<T>, data: &mut T) {
+        let frame = match self.graphics_state.current_frame() {
+            Ok(frame) => frame,
+            Err(e) => {
+                eprintln!("dropped frame: {:?}", e);
+                return;
+            }
+        };
+
+        let mut encoder = self
+            .graphics_state
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: &frame.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.background_color),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
         });
+
+        self.fps.start(TimingState::Draw);
+        let alpha = self.timestep.alpha();
+        for element in screen.iter_mut() {
+            element.render(self, data, alpha, &frame, &mut render_pass);
+            self.fps.advance();
+        }
+        std::mem::drop(render_pass);
+
+        self.graphics_state.queue.submit(Some(encoder.finish()));
+        self.graphics_state.present(frame);
     }
 }
 
@@ -127,20 +413,54 @@ impl AsRef<GraphicsState> for Engine {
     }
 }
 
+/// Tells the engine whether an event should keep travelling down the screen after an
+/// element has processed it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Propagation {
+    /// The element used the event, so the elements underneath it will not receive it.
+    Consumed,
+    /// The event is passed on to the next element.
+    PassThrough,
+}
+
 /// Represents items that have update events and draw to the screen.
 pub trait Element<Data> {
-    /// Process `winit` events.
-    fn update(&mut self, engine: &mut Engine, data: &mut Data, event: &Event<()>);
+    /// Process `winit` events. Elements are updated from the top of the screen down,
+    /// and returning [`Propagation::Consumed`] stops the event from reaching the
+    /// elements underneath this one. Events sent through an [`EventProxy`] arrive as
+    /// [`Event::UserEvent`].
+    fn update(
+        &mut self,
+        engine: &mut Engine,
+        data: &mut Data,
+        event: &Event<EngineEvent>,
+    ) -> Propagation;
+
+    /// Advance the simulation by one fixed step of `dt` seconds. This runs at the tick rate
+    /// set with [`EngineBuilder::tick_rate`], no matter how fast frames are being drawn.
+    fn tick(&mut self, _engine: &mut Engine, _data: &mut Data, _dt: f32) {}
+
+    /// Called when the screen containing this element is pushed onto the screen stack.
+    /// This is a good place to set up any GPU resources the element needs.
+    fn enter(&mut self, _engine: &mut Engine, _data: &mut Data) {}
+
+    /// Called when the screen containing this element is popped off of the screen stack,
+    /// either directly or by being replaced.
+    fn exit(&mut self, _engine: &mut Engine, _data: &mut Data) {}
 
     /// Draw to the screen. Note: it is expected that trait implementers will use
     /// the supplied render pass, however, to explain the lifetime annotations,
     /// the render pass is provided to all elements in the screen, so they all
     /// must live as long as the render pass.
+    ///
+    /// `alpha` is how far along this frame is between the previous simulation tick and
+    /// the next one, for interpolating between states.
     fn render<'a: 'rp, 'rp>(
         &'a mut self,
         engine: &mut Engine,
         data: &mut Data,
-        frame: &wgpu::SurfaceTexture,
+        alpha: f32,
+        frame: &Frame,
         render_pass: &mut wgpu::RenderPass<'rp>,
     );
 }
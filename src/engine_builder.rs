@@ -1,12 +1,27 @@
-use crate::winit::window::WindowBuilder;
+use std::path::PathBuf;
 
-use crate::Engine;
+use crate::winit::{dpi::PhysicalSize, window::WindowBuilder};
+
+use crate::graphics::GraphicsSettings;
+use crate::{wgpu, Engine, Error, RedrawMode};
 
 /// The tool that builds your engine for you.
-#[derive(Default)]
 pub struct EngineBuilder {
     pub(crate) window_builder: WindowBuilder,
-    pub bg_color: crate::wgpu::Color,
+    pub bg_color: wgpu::Color,
+    pub(crate) tick_rate: f32,
+    pub(crate) max_catch_up: u32,
+    pub(crate) target_fps: Option<f32>,
+    pub(crate) redraw_mode: RedrawMode,
+    pub(crate) headless: Option<PhysicalSize<u32>>,
+    pub(crate) graphics: GraphicsSettings,
+    pub(crate) shader_cache: Option<(PathBuf, String)>,
+}
+
+impl Default for EngineBuilder {
+    fn default() -> Self {
+        Self::new(|wb| wb)
+    }
 }
 
 impl EngineBuilder {
@@ -16,15 +31,123 @@ impl EngineBuilder {
         Self {
             window_builder: window_fn(WindowBuilder::new()),
             bg_color: Default::default(),
+            tick_rate: 60.0,
+            max_catch_up: 5,
+            target_fps: None,
+            redraw_mode: RedrawMode::Continuous,
+            headless: None,
+            graphics: GraphicsSettings::default(),
+            shader_cache: None,
         }
     }
 
-    pub fn bg_color(mut self, color: crate::wgpu::Color) -> Self {
+    pub fn bg_color(mut self, color: wgpu::Color) -> Self {
         self.bg_color = color;
         self
     }
 
-    pub fn build(self) -> Engine {
+    /// Set how many times per second [`Element::tick`](crate::Element::tick) is called.
+    /// Defaults to 60.
+    pub fn tick_rate(mut self, tick_rate: f32) -> Self {
+        assert!(tick_rate > 0.0, "Tick rate must be positive");
+        self.tick_rate = tick_rate;
+        self
+    }
+
+    /// Set the maximum number of ticks that can be run in a single frame when the
+    /// simulation falls behind. Any time past that is dropped. Defaults to 5.
+    pub fn max_catch_up(mut self, max_catch_up: u32) -> Self {
+        assert!(max_catch_up > 0, "Max catch up must be at least 1");
+        self.max_catch_up = max_catch_up;
+        self
+    }
+
+    /// Limit how many frames are drawn per second. Between frames, the engine sleeps
+    /// instead of polling for events, so it doesn't peg a CPU core. Unlimited by default.
+    pub fn target_fps(mut self, target_fps: f32) -> Self {
+        assert!(target_fps > 0.0, "Target FPS must be positive");
+        self.target_fps = Some(target_fps);
+        self
+    }
+
+    /// Set when the window is redrawn. Defaults to [`RedrawMode::Continuous`].
+    pub fn redraw_mode(mut self, redraw_mode: RedrawMode) -> Self {
+        self.redraw_mode = redraw_mode;
+        self
+    }
+
+    /// Run without a window, drawing into an offscreen texture of the given size instead.
+    /// The engine must then be driven with [`Engine::run_headless`]. This is mostly useful
+    /// for testing elements on machines without a display.
+    pub fn headless(mut self, width: u32, height: u32) -> Self {
+        self.headless = Some(PhysicalSize::new(width, height));
+        self
+    }
+
+    /// Set the [`wgpu::PresentMode`] used by the window's surface.
+    /// Defaults to [`wgpu::PresentMode::Immediate`].
+    pub fn present_mode(mut self, present_mode: wgpu::PresentMode) -> Self {
+        self.graphics.present_mode = present_mode;
+        self
+    }
+
+    /// Convenience function to turn vsync on or off. This picks [`wgpu::PresentMode::Fifo`]
+    /// when enabled, and [`wgpu::PresentMode::Immediate`] otherwise.
+    pub fn vsync(self, vsync: bool) -> Self {
+        self.present_mode(match vsync {
+            true => wgpu::PresentMode::Fifo,
+            false => wgpu::PresentMode::Immediate,
+        })
+    }
+
+    /// Set the texture format of the surface. If this isn't set, the surface's preferred
+    /// format is used.
+    pub fn surface_format(mut self, format: wgpu::TextureFormat) -> Self {
+        self.graphics.surface_format = Some(format);
+        self
+    }
+
+    /// Set which graphics APIs are allowed to be used. Defaults to [`wgpu::Backends::all`].
+    pub fn backends(mut self, backends: wgpu::Backends) -> Self {
+        self.graphics.backends = backends;
+        self
+    }
+
+    /// Set whether to prefer a low power or high performance adapter.
+    pub fn power_preference(mut self, power_preference: wgpu::PowerPreference) -> Self {
+        self.graphics.power_preference = power_preference;
+        self
+    }
+
+    /// Force the use of a fallback (software) adapter.
+    pub fn force_fallback_adapter(mut self, force_fallback_adapter: bool) -> Self {
+        self.graphics.force_fallback_adapter = force_fallback_adapter;
+        self
+    }
+
+    /// Set the [`wgpu::Features`] the device must support.
+    pub fn features(mut self, features: wgpu::Features) -> Self {
+        self.graphics.features = features;
+        self
+    }
+
+    /// Set the [`wgpu::Limits`] the device must support.
+    pub fn limits(mut self, limits: wgpu::Limits) -> Self {
+        self.graphics.limits = limits;
+        self
+    }
+
+    /// Keep compiled shaders in `dir`, so that startup doesn't have to compile every shader
+    /// again. The cache can't tell when the shader compiler changes, so `salt` has to; bump it
+    /// to throw away every cached shader. See
+    /// [`ShaderState::set_cache_dir`](crate::shaders::ShaderState::set_cache_dir).
+    pub fn shader_cache(mut self, dir: impl Into<PathBuf>, salt: impl Into<String>) -> Self {
+        self.shader_cache = Some((dir.into(), salt.into()));
+        self
+    }
+
+    /// Build the engine, creating the window and graphics device.
+    pub fn build(self) -> Result<Engine, Error> {
         Engine::new(self)
     }
 }
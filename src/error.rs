@@ -0,0 +1,68 @@
+use std::fmt;
+
+use crate::shaders::ShaderError;
+use crate::TagName;
+use crate::{wgpu, winit};
+
+/// Everything that can go wrong while setting up the engine or loading shaders.
+#[derive(Debug, derive_more::From)]
+pub enum Error {
+    /// No graphics adapter matching the requested options could be found.
+    #[from(ignore)]
+    NoAdapter,
+    /// The adapter was found, but refused to give us a device.
+    RequestDevice(wgpu::RequestDeviceError),
+    /// The window could not be created.
+    WindowCreation(winit::error::OsError),
+    /// The shader compiler could not be started.
+    #[from(ignore)]
+    ShaderCompiler,
+    /// A shader failed to compile.
+    #[from(ignore)]
+    Shader(Vec<ShaderError>),
+    /// A pipeline layout could not be generated from a set of shaders.
+    #[from(ignore)]
+    Reflection(String),
+    /// No shader is loaded with this tag, either because it was never loaded, it failed its
+    /// first compile, or it was unloaded.
+    #[from(ignore)]
+    MissingShader(TagName),
+    /// A file could not be read.
+    Io(std::io::Error),
+    /// The file watcher used for hot reloading could not be set up.
+    Watch(notify::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoAdapter => write!(f, "no compatible graphics adapter found"),
+            Error::RequestDevice(e) => write!(f, "unable to request a graphics device: {}", e),
+            Error::WindowCreation(e) => write!(f, "unable to create the window: {}", e),
+            Error::ShaderCompiler => write!(f, "unable to start the shader compiler"),
+            Error::Shader(errors) => {
+                write!(f, "failed to compile shader")?;
+                for error in errors {
+                    write!(f, "\n{}", error)?;
+                }
+                Ok(())
+            }
+            Error::Reflection(e) => write!(f, "unable to generate pipeline layout: {}", e),
+            Error::MissingShader(tag) => write!(f, "no shader is loaded as {}", tag),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Watch(e) => write!(f, "unable to watch shader files: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::RequestDevice(e) => Some(e),
+            Error::WindowCreation(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::Watch(e) => Some(e),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::any::Any;
+use std::fmt;
+
+use acidalia_core::{Tag, TagName};
+use crossbeam_channel::Sender;
+
+use crate::shaders::ShaderError;
+use crate::winit::event_loop::EventLoopProxy;
+
+/// Custom events that are sent through the event loop, and handed to elements as
+/// [`Event::UserEvent`](crate::winit::event::Event::UserEvent).
+pub enum EngineEvent {
+    /// A shader was recompiled after its source changed, and the pipelines using it
+    /// have been rebuilt.
+    ShaderReloaded(Tag),
+    /// A shader failed to compile. If it had compiled before, the last working version is
+    /// kept in use.
+    ShaderFailed(Tag, Vec<ShaderError>),
+    /// An event sent by the game, e.g. from an asset loading or networking thread.
+    User(Box<dyn Any + Send>),
+}
+
+impl EngineEvent {
+    /// Get the contents of a [`EngineEvent::User`] event, if it holds a `T`.
+    pub fn user<T: Any>(&self) -> Option<&T> {
+        match self {
+            EngineEvent::User(event) => event.downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for EngineEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineEvent::ShaderReloaded(tag) => f
+                .debug_tuple("ShaderReloaded")
+                .field(&TagName(*tag))
+                .finish(),
+            EngineEvent::ShaderFailed(tag, errors) => f
+                .debug_tuple("ShaderFailed")
+                .field(&TagName(*tag))
+                .field(errors)
+                .finish(),
+            EngineEvent::User(_) => f.debug_tuple("User").finish_non_exhaustive(),
+        }
+    }
+}
+
+/// A handle for sending [`EngineEvent`]s to the engine from any thread. Sending an event
+/// wakes up the event loop.
+#[derive(Clone)]
+pub struct EventProxy {
+    inner: ProxyInner,
+}
+
+#[derive(Clone)]
+enum ProxyInner {
+    Window(EventLoopProxy<EngineEvent>),
+    // Headless engines don't have an event loop, so events are queued up and handed out
+    // at the start of the next frame.
+    Headless(Sender<EngineEvent>),
+}
+
+impl EventProxy {
+    pub(crate) fn window(proxy: EventLoopProxy<EngineEvent>) -> Self {
+        Self {
+            inner: ProxyInner::Window(proxy),
+        }
+    }
+
+    pub(crate) fn headless(tx: Sender<EngineEvent>) -> Self {
+        Self {
+            inner: ProxyInner::Headless(tx),
+        }
+    }
+
+    /// Send an event to the engine. If the engine has already stopped, the event is
+    /// handed back.
+    pub fn send(&self, event: EngineEvent) -> Result<(), EngineEvent> {
+        match &self.inner {
+            ProxyInner::Window(proxy) => proxy.send_event(event).map_err(|e| e.0),
+            ProxyInner::Headless(tx) => tx.send(event).map_err(|e| e.0),
+        }
+    }
+
+    /// Convenience function to send an [`EngineEvent::User`] event.
+    pub fn send_user(&self, event: impl Any + Send) -> Result<(), EngineEvent> {
+        self.send(EngineEvent::User(Box::new(event)))
+    }
+}
@@ -0,0 +1,15 @@
+// Copies the offscreen target onto the window's surface when frames are being captured.
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> [[builtin(position)]] vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+[[group(0), binding(0)]]
+var captured: texture_2d<f32>;
+
+[[stage(fragment)]]
+fn fs_main([[builtin(position)]] position: vec4<f32>) -> [[location(0)]] vec4<f32> {
+    return textureLoad(captured, vec2<i32>(i32(position.x), i32(position.y)), 0);
+}
@@ -5,6 +5,8 @@ use crate::wgpu::{self};
 use crate::winit;
 use futures;
 
+use crate::capture::Capture;
+use crate::Error;
 use futures::executor::block_on;
 use wgpu::{
     Backends, BindGroup, BindGroupEntry, BindGroupLayout, BindGroupLayoutEntry, BindingResource,
@@ -13,68 +15,257 @@ use wgpu::{
 };
 use winit::dpi::PhysicalSize;
 
+/// Options for picking an adapter and device, and setting up the surface.
+/// These are set through the [`EngineBuilder`](crate::EngineBuilder).
+pub(crate) struct GraphicsSettings {
+    pub present_mode: wgpu::PresentMode,
+    pub surface_format: Option<wgpu::TextureFormat>,
+    pub backends: Backends,
+    pub power_preference: wgpu::PowerPreference,
+    pub force_fallback_adapter: bool,
+    pub features: wgpu::Features,
+    pub limits: wgpu::Limits,
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self {
+            present_mode: wgpu::PresentMode::Immediate,
+            surface_format: None,
+            backends: Backends::all(),
+            power_preference: wgpu::PowerPreference::default(),
+            force_fallback_adapter: false,
+            features: wgpu::Features::empty(),
+            limits: wgpu::Limits::default(),
+        }
+    }
+}
+
 /// A struct containing everything necessary to interact with wgpu.
 pub struct GraphicsState {
     pub instance: wgpu::Instance,
-    pub surface: wgpu::Surface,
+    /// The window's surface. This is `None` when running headless.
+    pub surface: Option<wgpu::Surface>,
     pub adapter: wgpu::Adapter,
     pub device: Arc<wgpu::Device>,
     pub queue: wgpu::Queue,
     pub swapchain_descriptor: wgpu::SurfaceConfiguration,
 
+    offscreen: Option<Arc<wgpu::Texture>>,
+    capturing: bool,
+    blit: Option<Blit>,
     size: winit::dpi::PhysicalSize<u32>,
+    format_listeners: Vec<Box<dyn Fn(wgpu::TextureFormat)>>,
 }
 
 impl GraphicsState {
     /// Creates a new `GraphicsState` from a `winit` window.
-    pub(crate) fn new(window: &winit::window::Window) -> Self {
-        let size = window.inner_size();
-        let instance = wgpu::Instance::new(Backends::all());
+    pub(crate) fn new(
+        window: &winit::window::Window,
+        settings: &GraphicsSettings,
+    ) -> Result<Self, Error> {
+        let instance = wgpu::Instance::new(settings.backends);
         let surface = unsafe { instance.create_surface(window) };
+        Self::with_target(instance, Some(surface), window.inner_size(), settings)
+    }
 
+    /// Creates a new `GraphicsState` that renders into an offscreen texture of the given `size`
+    /// instead of a window.
+    pub(crate) fn new_headless(
+        size: winit::dpi::PhysicalSize<u32>,
+        settings: &GraphicsSettings,
+    ) -> Result<Self, Error> {
+        let instance = wgpu::Instance::new(settings.backends);
+        Self::with_target(instance, None, size, settings)
+    }
+
+    fn with_target(
+        instance: wgpu::Instance,
+        surface: Option<wgpu::Surface>,
+        size: winit::dpi::PhysicalSize<u32>,
+        settings: &GraphicsSettings,
+    ) -> Result<Self, Error> {
         let adapter_options = wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::default(),
-            compatible_surface: Some(&surface),
-            force_fallback_adapter: false,
+            power_preference: settings.power_preference,
+            compatible_surface: surface.as_ref(),
+            force_fallback_adapter: settings.force_fallback_adapter,
         };
 
-        let adapter = block_on(async { instance.request_adapter(&adapter_options).await.unwrap() });
+        let adapter =
+            block_on(instance.request_adapter(&adapter_options)).ok_or(Error::NoAdapter)?;
 
-        let (device, queue) = block_on(async {
-            adapter
-                .request_device(&wgpu::DeviceDescriptor::default(), None)
-                .await
-                .unwrap()
-        });
+        let device_descriptor = wgpu::DeviceDescriptor {
+            label: None,
+            features: settings.features,
+            limits: settings.limits.clone(),
+        };
+        let (device, queue) = block_on(adapter.request_device(&device_descriptor, None))?;
         let device = Arc::new(device);
 
+        let format = match (&surface, settings.surface_format) {
+            (_, Some(format)) => format,
+            (Some(surface), None) => surface
+                .get_preferred_format(&adapter)
+                .unwrap_or(wgpu::TextureFormat::Bgra8UnormSrgb),
+            (None, None) => wgpu::TextureFormat::Rgba8UnormSrgb,
+        };
         let swapchain_descriptor = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            format,
             width: size.width,
             height: size.height,
-            present_mode: wgpu::PresentMode::Immediate,
+            present_mode: settings.present_mode,
         };
-        surface.configure(&device, &swapchain_descriptor);
 
-        Self {
+        let mut ret = Self {
             instance,
             surface,
             adapter,
             device,
             queue,
             swapchain_descriptor,
+            offscreen: None,
+            capturing: false,
+            blit: None,
             size,
+            format_listeners: vec![],
+        };
+        ret.configure();
+        Ok(ret)
+    }
+
+    /// Apply the swapchain descriptor to the surface, and recreate the offscreen target
+    /// to match it when running headless or capturing frames.
+    fn configure(&mut self) {
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.swapchain_descriptor);
+        }
+        if self.surface.is_some() && !self.capturing {
+            self.offscreen = None;
+            self.blit = None;
+            return;
+        }
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen target"),
+            size: self.size.to_extent(1),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.swapchain_descriptor.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+        });
+        if self.surface.is_some() {
+            self.blit = Some(Blit::new(
+                &self.device,
+                &texture,
+                self.swapchain_descriptor.format,
+            ));
+        }
+        self.offscreen = Some(Arc::new(texture));
+    }
+
+    /// Whether this state renders to an offscreen texture rather than a window.
+    pub fn is_headless(&self) -> bool {
+        self.surface.is_none()
+    }
+
+    /// Grab the next frame to draw to.
+    pub(crate) fn current_frame(&self) -> Result<Frame, wgpu::SurfaceError> {
+        let output = match (&self.surface, &self.offscreen) {
+            (Some(surface), Some(texture)) => {
+                FrameOutput::Captured(surface.get_current_texture()?, Arc::clone(texture))
+            }
+            (Some(surface), None) => FrameOutput::Surface(surface.get_current_texture()?),
+            (None, Some(texture)) => FrameOutput::Offscreen(Arc::clone(texture)),
+            (None, None) => unreachable!("Headless graphics state has no offscreen target"),
+        };
+        let view = output
+            .texture()
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        Ok(Frame { output, view })
+    }
+
+    /// Present a finished `frame` to the window, first copying it over from the offscreen
+    /// target if frames are being captured. This does nothing for headless frames.
+    pub(crate) fn present(&self, frame: Frame) {
+        match frame.output {
+            FrameOutput::Surface(output) => output.present(),
+            FrameOutput::Captured(output, _) => {
+                let blit = self
+                    .blit
+                    .as_ref()
+                    .expect("Capturing without a blit pipeline");
+                let view = output
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+                let mut encoder = self.command_encoder("capture blit encoder");
+                blit.draw(&mut encoder, &view);
+                self.queue.submit(Some(encoder.finish()));
+                output.present();
+            }
+            FrameOutput::Offscreen(_) => {}
         }
     }
 
+    /// Whether frames drawn to the window are kept around for [`GraphicsState::capture_frame`].
+    pub fn is_capturing(&self) -> bool {
+        self.surface.is_none() || self.capturing
+    }
+
+    /// Start or stop keeping the frames drawn to the window around, so they can be read back
+    /// with [`GraphicsState::capture_frame`]. While this is on every frame is drawn to an
+    /// offscreen target first and then copied onto the window, which costs an extra pass.
+    /// Headless frames can always be captured, so this does nothing when running headless.
+    pub fn set_capturing(&mut self, capturing: bool) {
+        if capturing == self.capturing {
+            return;
+        }
+        self.capturing = capturing;
+        self.configure();
+    }
+
     /// Sets the size and updates the swapchain & descriptor.
     pub(crate) fn set_size(&mut self, size: winit::dpi::PhysicalSize<u32>) {
         self.size = size;
         self.swapchain_descriptor.width = size.width;
         self.swapchain_descriptor.height = size.height;
-        self.surface
-            .configure(&self.device, &self.swapchain_descriptor);
+        self.configure();
+    }
+
+    /// Change how frames are presented to the window, e.g. to turn vsync on or off.
+    pub fn set_present_mode(&mut self, present_mode: wgpu::PresentMode) {
+        self.swapchain_descriptor.present_mode = present_mode;
+        self.configure();
+    }
+
+    /// Convenience function to turn vsync on or off. This picks [`wgpu::PresentMode::Fifo`]
+    /// when enabled, and [`wgpu::PresentMode::Immediate`] otherwise.
+    pub fn set_vsync(&mut self, vsync: bool) {
+        self.set_present_mode(match vsync {
+            true => wgpu::PresentMode::Fifo,
+            false => wgpu::PresentMode::Immediate,
+        });
+    }
+
+    /// Change the texture format of the surface. Every pipeline built through the shader
+    /// manufactory that targets the surface is rebuilt to use the new format before this
+    /// returns, so it can be drawn with straight away.
+    pub fn set_surface_format(&mut self, format: wgpu::TextureFormat) {
+        if format == self.swapchain_descriptor.format {
+            return;
+        }
+        self.swapchain_descriptor.format = format;
+        self.configure();
+        for listener in self.format_listeners.iter() {
+            listener(format);
+        }
+    }
+
+    /// Register a function to be called whenever the surface format changes.
+    pub fn on_format_change(&mut self, listener: impl Fn(wgpu::TextureFormat) + 'static) {
+        self.format_listeners.push(Box::new(listener));
     }
 
     /// Gets the swapchain's frame size.
@@ -131,6 +322,87 @@ impl GraphicsState {
             })
     }
 
+    /// Copy the last frame drawn into a [`Capture`]. When rendering to a window this returns
+    /// `None` unless capturing was turned on with [`GraphicsState::set_capturing`], as the
+    /// frame is gone once it has been presented.
+    pub fn capture_frame(&self) -> Option<Capture> {
+        self.offscreen.as_ref().map(|texture| {
+            self.capture_texture(
+                texture,
+                self.size.to_extent(1),
+                self.swapchain_descriptor.format,
+            )
+        })
+    }
+
+    /// Copy the first layer of a `texture` with the given `size` and `format` into a [`Capture`],
+    /// blocking until the GPU is done. The texture must have been created with
+    /// [`wgpu::TextureUsages::COPY_SRC`].
+    ///
+    /// # Panics
+    /// If `format` isn't one of the 8-bit RGBA or BGRA formats.
+    pub fn capture_texture(
+        &self,
+        texture: &wgpu::Texture,
+        size: wgpu::Extent3d,
+        format: wgpu::TextureFormat,
+    ) -> Capture {
+        let swizzle = match format {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            _ => panic!("Unable to capture textures with format {:?}", format),
+        };
+        let unpadded_row = size.width * 4;
+        let padded_row = unpadded_row.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("capture buffer"),
+            size: padded_row as u64 * size.height as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self.command_encoder("capture encoder");
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(padded_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                depth_or_array_layers: 1,
+                ..size
+            },
+        );
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        self.device.poll(wgpu::Maintain::Wait);
+        block_on(mapping).expect("Unable to map the capture buffer");
+
+        let mut data = Vec::with_capacity((unpadded_row * size.height) as usize);
+        for row in slice.get_mapped_range().chunks(padded_row as usize) {
+            data.extend_from_slice(&row[..unpadded_row as usize]);
+        }
+        buffer.unmap();
+
+        if swizzle {
+            for pixel in data.chunks_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        Capture {
+            width: size.width,
+            height: size.height,
+            data,
+        }
+    }
+
     pub fn with_encoder(&mut self, f: impl Fn(&mut CommandEncoder)) {
         let mut encoder = self
             .device
@@ -140,6 +412,101 @@ impl GraphicsState {
     }
 }
 
+enum FrameOutput {
+    Surface(wgpu::SurfaceTexture),
+    /// A window frame that is drawn to the offscreen target and copied over on present.
+    Captured(wgpu::SurfaceTexture, Arc<wgpu::Texture>),
+    Offscreen(Arc<wgpu::Texture>),
+}
+
+impl FrameOutput {
+    fn texture(&self) -> &wgpu::Texture {
+        match self {
+            FrameOutput::Surface(frame) => &frame.texture,
+            FrameOutput::Captured(_, texture) | FrameOutput::Offscreen(texture) => texture,
+        }
+    }
+}
+
+/// The texture currently being drawn to. This is either the window's surface texture,
+/// or the offscreen target when running headless or capturing frames.
+pub struct Frame {
+    output: FrameOutput,
+    /// A view of the whole frame texture.
+    pub view: wgpu::TextureView,
+}
+
+impl Frame {
+    /// The texture backing this frame.
+    pub fn texture(&self) -> &wgpu::Texture {
+        self.output.texture()
+    }
+}
+
+/// Draws the offscreen target onto the window's surface.
+struct Blit {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: BindGroup,
+}
+
+impl Blit {
+    fn new(device: &Device, source: &wgpu::Texture, format: wgpu::TextureFormat) -> Self {
+        let module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("capture blit shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("gl/blit.wgsl").into()),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("capture blit pipeline"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[format.into()],
+            }),
+            multiview: None,
+        });
+        let view = source.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("capture blit bind group"),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&view),
+            }],
+        });
+        Self {
+            pipeline,
+            bind_group,
+        }
+    }
+
+    fn draw(&self, encoder: &mut CommandEncoder, target: &wgpu::TextureView) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("capture blit pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
 /// Convenience trait to convert something into an [`wgpu::Extent3d`].
 pub trait ToExtent {
     /// Do the conversion.
@@ -1,18 +1,35 @@
 // TODO: uncomment this
 // #![forbid(missing_docs)]
 
+// Lets the derives, which refer to everything through `::acidalia`, be used in here too.
+extern crate self as acidalia;
+
+/// Reading rendered images back from the GPU, and comparing them against golden images.
+pub mod capture;
 #[macro_use]
 mod engine;
 mod engine_builder;
+mod error;
+mod event;
 mod fps;
 pub mod graphics;
+mod pacing;
+mod reflect;
+mod shader_cache;
 /// Everything related to managing shaders.
 pub mod shaders;
-pub use shaderc::ShaderKind;
+mod timestep;
+mod vertex;
+pub use shaderc::{EnvVersion, OptimizationLevel, ShaderKind, TargetEnv};
 
 pub use engine::*;
 pub use engine_builder::EngineBuilder;
-pub use graphics::GraphicsState;
+pub use error::Error;
+pub use event::{EngineEvent, EventProxy};
+pub use graphics::{Frame, GraphicsState};
+pub use pacing::{FramePacer, RedrawMode};
+pub use timestep::FixedTimestep;
+pub use vertex::{Vertex, VertexAttributeFormat, VertexLayout};
 
 pub use wgpu;
 pub use winit;
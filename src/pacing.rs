@@ -0,0 +1,90 @@
+use std::time::{Duration, Instant};
+
+/// How close to a frame's deadline the engine stops sleeping and starts spinning. Sleeping
+/// is only accurate to around a millisecond on most platforms, so the last stretch is
+/// waited out by busy looping instead.
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+
+/// Decides when the engine redraws the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RedrawMode {
+    /// Draw frames back to back, as fast as the frame limit allows.
+    #[default]
+    Continuous,
+    /// Only draw when a window event or an [`EngineEvent`](crate::EngineEvent) arrives, or
+    /// when an element asks for it with [`Engine::request_redraw`](crate::Engine::request_redraw).
+    /// The simulation only ticks while the engine is awake, so this is best suited to
+    /// tool-style apps.
+    Reactive,
+}
+
+/// Limits how often frames are drawn, so the engine doesn't spin as fast as the GPU lets it.
+pub struct FramePacer {
+    interval: Option<Duration>,
+    next_frame: Instant,
+}
+
+impl FramePacer {
+    pub(crate) fn new(target_fps: Option<f32>) -> Self {
+        let mut pacer = Self {
+            interval: None,
+            next_frame: Instant::now(),
+        };
+        pacer.set_target_fps(target_fps);
+        pacer
+    }
+
+    /// Set the maximum number of frames drawn per second, or `None` to remove the limit.
+    ///
+    /// # Panics
+    /// If the target is not positive.
+    pub fn set_target_fps(&mut self, target_fps: Option<f32>) {
+        if let Some(fps) = target_fps {
+            assert!(fps > 0.0, "Target FPS must be positive");
+        }
+        self.interval = target_fps.map(|fps| Duration::from_secs_f32(1.0 / fps));
+        self.next_frame = Instant::now();
+    }
+
+    /// The maximum number of frames drawn per second, if there is a limit.
+    pub fn target_fps(&self) -> Option<f32> {
+        self.interval.map(|interval| 1.0 / interval.as_secs_f32())
+    }
+
+    /// When the engine should wake up to prepare the next frame, or `None` if there is no
+    /// frame limit.
+    pub(crate) fn wake_time(&self) -> Option<Instant> {
+        self.interval.map(|_| {
+            self.next_frame
+                .checked_sub(SPIN_MARGIN)
+                .unwrap_or(self.next_frame)
+        })
+    }
+
+    /// Whether it's close enough to the next frame to start drawing it. If it is, this
+    /// spins until the frame is due.
+    pub(crate) fn ready(&self) -> bool {
+        match self.wake_time() {
+            Some(wake) if Instant::now() < wake => false,
+            Some(_) => {
+                while Instant::now() < self.next_frame {
+                    std::hint::spin_loop();
+                }
+                true
+            }
+            None => true,
+        }
+    }
+
+    /// Schedule the next frame, after one has been drawn. If the engine has fallen behind,
+    /// the schedule is restarted from now instead of trying to catch up.
+    pub(crate) fn frame_drawn(&mut self) {
+        if let Some(interval) = self.interval {
+            let now = Instant::now();
+            self.next_frame += interval;
+            if self.next_frame < now {
+                self.next_frame = now + interval;
+            }
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::BTreeMap;
+
+use crate::wgpu;
+
+/// A resource that a shader expects to find in a bind group.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReflectedBinding {
+    pub group: u32,
+    pub binding: u32,
+    pub visibility: wgpu::ShaderStages,
+    pub ty: wgpu::BindingType,
+}
+
+/// What a compiled shader expects its pipeline layout to look like.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShaderReflection {
+    pub bindings: Vec<ReflectedBinding>,
+    pub push_constants: Option<wgpu::PushConstantRange>,
+}
+
+/// Layouts generated from the resources that a set of shaders use. The bind group layouts
+/// are in group order, and are needed to create bind groups that fit the pipeline.
+#[derive(Debug)]
+pub struct ReflectedLayout {
+    pub bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+    pub pipeline_layout: wgpu::PipelineLayout,
+}
+
+impl ShaderReflection {
+    /// Reflect the resources used by a SPIR-V module.
+    pub(crate) fn from_spirv(words: &[u32], entry_point: &str) -> Result<Self, String> {
+        let options = naga::front::spv::Options::default();
+        let module = naga::front::spv::Parser::new(words.iter().cloned(), &options)
+            .parse()
+            .map_err(|e| format!("Unable to parse SPIR-V: {:?}", e))?;
+        // shaderc has already checked the module, so this is only here to find out which
+        // globals each entry point uses.
+        let info = naga::valid::Validator::new(
+            naga::valid::ValidationFlags::empty(),
+            naga::valid::Capabilities::all(),
+        )
+        .validate(&module)
+        .map_err(|e| format!("Unable to analyze SPIR-V: {}", e))?;
+        Self::from_module(&module, &info, entry_point)
+    }
+
+    /// Reflect the resources in `module` that `entry_point` uses, as found by validating it
+    /// into `info`. The entry point decides which stage they're visible to.
+    pub(crate) fn from_module(
+        module: &naga::Module,
+        info: &naga::valid::ModuleInfo,
+        entry_point: &str,
+    ) -> Result<Self, String> {
+        let (index, ep) = module
+            .entry_points
+            .iter()
+            .enumerate()
+            .find(|(_, ep)| ep.name == entry_point)
+            .ok_or_else(|| format!("No entry point named '{}'", entry_point))?;
+        let stage = match ep.stage {
+            naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+            naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+            naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+        };
+        let uses = info.get_entry_point(index);
+        let mut layouter = naga::proc::Layouter::default();
+        layouter
+            .update(&module.types, &module.constants)
+            .map_err(|e| format!("Unable to lay out types: {:?}", e))?;
+        let mut reflection = Self::default();
+        for (handle, var) in module.global_variables.iter() {
+            // Modules often declare everything that any of their entry points need.
+            if uses[handle].is_empty() {
+                continue;
+            }
+            if var.class == naga::StorageClass::PushConstant {
+                reflection.push_constants = Some(wgpu::PushConstantRange {
+                    stages: stage,
+                    range: 0..layouter[var.ty].size,
+                });
+                continue;
+            }
+            let binding = match &var.binding {
+                Some(binding) => binding,
+                None => continue,
+            };
+            let ty = match var.class {
+                naga::StorageClass::Uniform => wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                naga::StorageClass::Storage { access } => wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage {
+                        read_only: !access.contains(naga::StorageAccess::STORE),
+                    },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                naga::StorageClass::Handle => handle_binding(&module.types[var.ty].inner)?,
+                _ => continue,
+            };
+            reflection.bindings.push(ReflectedBinding {
+                group: binding.group,
+                binding: binding.binding,
+                visibility: stage,
+                ty,
+            });
+        }
+        Ok(reflection)
+    }
+}
+
+/// Work out the binding type of a texture or sampler.
+fn handle_binding(inner: &naga::TypeInner) -> Result<wgpu::BindingType, String> {
+    match *inner {
+        naga::TypeInner::Sampler { comparison } => {
+            Ok(wgpu::BindingType::Sampler(match comparison {
+                true => wgpu::SamplerBindingType::Comparison,
+                false => wgpu::SamplerBindingType::Filtering,
+            }))
+        }
+        naga::TypeInner::Image {
+            dim,
+            arrayed,
+            class,
+        } => {
+            let view_dimension = match (dim, arrayed) {
+                (naga::ImageDimension::D1, false) => wgpu::TextureViewDimension::D1,
+                (naga::ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+                (naga::ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+                (naga::ImageDimension::D3, false) => wgpu::TextureViewDimension::D3,
+                (naga::ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+                (naga::ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+                (dim, true) => return Err(format!("Arrayed {:?} textures aren't supported", dim)),
+            };
+            Ok(match class {
+                naga::ImageClass::Sampled { kind, multi } => wgpu::BindingType::Texture {
+                    sample_type: match kind {
+                        naga::ScalarKind::Float => {
+                            wgpu::TextureSampleType::Float { filterable: true }
+                        }
+                        naga::ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                        naga::ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                        naga::ScalarKind::Bool => {
+                            return Err("Boolean textures aren't supported".to_owned())
+                        }
+                    },
+                    view_dimension,
+                    multisampled: multi,
+                },
+                naga::ImageClass::Depth { multi } => wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension,
+                    multisampled: multi,
+                },
+                naga::ImageClass::Storage { format, access } => wgpu::BindingType::StorageTexture {
+                    access: match (
+                        access.contains(naga::StorageAccess::LOAD),
+                        access.contains(naga::StorageAccess::STORE),
+                    ) {
+                        (true, true) => wgpu::StorageTextureAccess::ReadWrite,
+                        (false, true) => wgpu::StorageTextureAccess::WriteOnly,
+                        _ => wgpu::StorageTextureAccess::ReadOnly,
+                    },
+                    format: storage_format(format),
+                    view_dimension,
+                },
+            })
+        }
+        ref other => Err(format!("Unsupported resource type {:?}", other)),
+    }
+}
+
+macro_rules! storage_formats {
+    ($($format:ident),* $(,)?) => {
+        fn storage_format(format: naga::StorageFormat) -> wgpu::TextureFormat {
+            match format {
+                $(naga::StorageFormat::$format => wgpu::TextureFormat::$format,)*
+            }
+        }
+    };
+}
+
+storage_formats! {
+    R8Unorm, R8Snorm, R8Uint, R8Sint, R16Uint, R16Sint, R16Float, Rg8Unorm, Rg8Snorm,
+    Rg8Uint, Rg8Sint, R32Uint, R32Sint, R32Float, Rg16Uint, Rg16Sint, Rg16Float, Rgba8Unorm,
+    Rgba8Snorm, Rgba8Uint, Rgba8Sint, Rgb10a2Unorm, Rg11b10Float, Rg32Uint, Rg32Sint,
+    Rg32Float, Rgba16Uint, Rgba16Sint, Rgba16Float, Rgba32Uint, Rgba32Sint, Rgba32Float,
+}
+
+/// Create the bind group layouts and pipeline layout that fit every shader in `shaders`.
+/// A resource used by several stages is made visible to all of them, and any group that
+/// none of the shaders use gets an empty layout.
+pub(crate) fn create_layout(
+    device: &wgpu::Device,
+    label: Option<&str>,
+    shaders: &[&ShaderReflection],
+) -> Result<ReflectedLayout, String> {
+    let mut groups: BTreeMap<u32, BTreeMap<u32, wgpu::BindGroupLayoutEntry>> = BTreeMap::new();
+    for reflected in shaders.iter().flat_map(|s| s.bindings.iter()) {
+        let entry = groups
+            .entry(reflected.group)
+            .or_default()
+            .entry(reflected.binding)
+            .or_insert(wgpu::BindGroupLayoutEntry {
+                binding: reflected.binding,
+                visibility: wgpu::ShaderStages::empty(),
+                ty: reflected.ty,
+                count: None,
+            });
+        if entry.ty != reflected.ty {
+            return Err(format!(
+                "Binding {} in group {} is declared as both {:?} and {:?}",
+                reflected.binding, reflected.group, entry.ty, reflected.ty
+            ));
+        }
+        entry.visibility |= reflected.visibility;
+    }
+    let group_count = groups.keys().next_back().map_or(0, |group| group + 1);
+    let bind_group_layouts: Vec<wgpu::BindGroupLayout> = (0..group_count)
+        .map(|group| {
+            let entries: Vec<wgpu::BindGroupLayoutEntry> = groups
+                .get(&group)
+                .map(|entries| entries.values().cloned().collect())
+                .unwrap_or_default();
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label,
+                entries: &entries,
+            })
+        })
+        .collect();
+    // Every stage shares one block of push constants, which has to be big enough for
+    // whichever stage uses the most of it.
+    let push_constants = shaders
+        .iter()
+        .filter_map(|s| s.push_constants.as_ref())
+        .fold(None, |acc: Option<wgpu::PushConstantRange>, range| {
+            Some(match acc {
+                Some(acc) => wgpu::PushConstantRange {
+                    stages: acc.stages | range.stages,
+                    range: 0..acc.range.end.max(range.range.end),
+                },
+                None => range.clone(),
+            })
+        });
+    let layout_refs: Vec<&wgpu::BindGroupLayout> = bind_group_layouts.iter().collect();
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label,
+        bind_group_layouts: &layout_refs,
+        push_constant_ranges: push_constants.as_slice(),
+    });
+    Ok(ReflectedLayout {
+        bind_group_layouts,
+        pipeline_layout,
+    })
+}
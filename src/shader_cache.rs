@@ -0,0 +1,214 @@
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+
+use acidalia_core::StableHasher;
+
+/// Marks the start of every cache entry.
+const MAGIC: &[u8; 4] = b"ACSC";
+/// Bumped whenever the layout of an entry changes, so that old entries get ignored.
+const FORMAT_VERSION: u32 = 1;
+
+/// Compiled SPIR-V saved on disk, so that shaders which haven't changed don't need to be
+/// compiled again the next time the engine starts.
+///
+/// Entries are named after a key that covers the shader source and everything else that
+/// affects how it compiles. They also record the hash of every file the shader included,
+/// since those can change without the shader itself changing.
+#[derive(Clone, Debug)]
+pub(crate) struct ShaderCache {
+    dir: PathBuf,
+    /// Mixed into every key, so that entries from a different compiler can be told apart.
+    pub(crate) salt: String,
+}
+
+impl ShaderCache {
+    /// Use `dir` as the cache, creating it if needed.
+    pub(crate) fn new(dir: impl AsRef<Path>, salt: impl Into<String>) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir: std::fs::canonicalize(dir)?,
+            salt: salt.into(),
+        })
+    }
+
+    fn entry_path(&self, key: u128) -> PathBuf {
+        self.dir.join(format!("{:032x}.spv", key))
+    }
+
+    /// Load the entry for `key`, as long as it exists and none of its includes have changed
+    /// since it was stored. The includes are added to `includes`.
+    pub(crate) fn load(&self, key: u128, includes: &mut Vec<PathBuf>) -> Option<Vec<u32>> {
+        let data = std::fs::read(self.entry_path(key)).ok()?;
+        let mut reader = Reader(&data);
+        if reader.take(4)? != MAGIC || reader.u32()? != FORMAT_VERSION {
+            return None;
+        }
+        let mut found = vec![];
+        for _ in 0..reader.u32()? {
+            let len = reader.u32()? as usize;
+            let path = PathBuf::from(std::str::from_utf8(reader.take(len)?).ok()?);
+            let hash = u128::from_le_bytes(reader.take(16)?.try_into().ok()?);
+            if hash_file(&path)? != hash {
+                return None;
+            }
+            found.push(path);
+        }
+        if reader.0.len() % 4 != 0 {
+            return None;
+        }
+        let words = reader
+            .0
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+        includes.extend(found);
+        Some(words)
+    }
+
+    /// Save `spirv` as the entry for `key`. The cache is only there to speed things up, so
+    /// failing to write to it is reported but otherwise ignored.
+    pub(crate) fn store(&self, key: u128, includes: &[PathBuf], spirv: &[u32]) {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        data.extend_from_slice(&(includes.len() as u32).to_le_bytes());
+        for path in includes {
+            // An include that can't be recorded would make the entry impossible to check,
+            // so it's better not to store one at all.
+            let (name, hash) = match (path.to_str(), hash_file(path)) {
+                (Some(name), Some(hash)) => (name, hash),
+                _ => return,
+            };
+            data.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            data.extend_from_slice(name.as_bytes());
+            data.extend_from_slice(&hash.to_le_bytes());
+        }
+        for word in spirv {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        // Write to a temporary file first, so that crashing halfway through can't leave a
+        // broken entry behind.
+        let path = self.entry_path(key);
+        let tmp = path.with_extension("tmp");
+        if let Err(e) = std::fs::write(&tmp, &data).and_then(|_| std::fs::rename(&tmp, &path)) {
+            // TODO: log
+            eprintln!("Unable to write to the shader cache: {}", e);
+        }
+    }
+}
+
+/// Hash the contents of a file, or `None` if it can't be read.
+fn hash_file(path: &Path) -> Option<u128> {
+    let data = std::fs::read(path).ok()?;
+    let mut hasher = StableHasher::new();
+    hasher.write(&data);
+    Some(hasher.finish128())
+}
+
+/// Reads the fields of a cache entry in order.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh cache in its own temporary directory, removed again when dropped.
+    struct TempCache(ShaderCache);
+
+    impl TempCache {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "acidalia-shader-cache-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&dir);
+            Self(ShaderCache::new(dir, "test").unwrap())
+        }
+    }
+
+    impl Drop for TempCache {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0.dir);
+        }
+    }
+
+    const SPIRV: &[u32] = &[0x0723_0203, 0x0001_0000, 0, 42, 0xdead_beef];
+
+    #[test]
+    fn round_trip() {
+        let cache = TempCache::new("round-trip");
+        let include = cache.0.dir.join("common.glsl");
+        std::fs::write(&include, "float shared;").unwrap();
+
+        cache.0.store(1, std::slice::from_ref(&include), SPIRV);
+        let mut includes = vec![];
+        assert_eq!(cache.0.load(1, &mut includes).as_deref(), Some(SPIRV));
+        assert_eq!(includes, [include]);
+
+        let mut includes = vec![];
+        assert_eq!(cache.0.load(2, &mut includes), None);
+        assert!(includes.is_empty());
+    }
+
+    #[test]
+    fn changed_include_invalidates() {
+        let cache = TempCache::new("changed-include");
+        let include = cache.0.dir.join("common.glsl");
+        std::fs::write(&include, "float shared;").unwrap();
+        cache.0.store(1, std::slice::from_ref(&include), SPIRV);
+
+        std::fs::write(&include, "float shared = 1.0;").unwrap();
+        let mut includes = vec![];
+        assert_eq!(cache.0.load(1, &mut includes), None);
+        assert!(includes.is_empty());
+
+        std::fs::remove_file(&include).unwrap();
+        assert_eq!(cache.0.load(1, &mut includes), None);
+    }
+
+    #[test]
+    fn corrupt_entry_is_ignored() {
+        let cache = TempCache::new("corrupt");
+        let include = cache.0.dir.join("common.glsl");
+        std::fs::write(&include, "float shared;").unwrap();
+        cache.0.store(1, &[include], SPIRV);
+        let path = cache.0.entry_path(1);
+        let data = std::fs::read(&path).unwrap();
+
+        // Cut off partway through the include list, then partway through a SPIR-V word.
+        for len in [0, 6, 20, data.len() - 2] {
+            std::fs::write(&path, &data[..len]).unwrap();
+            assert_eq!(cache.0.load(1, &mut vec![]), None, "truncated to {}", len);
+        }
+
+        let mut bad_magic = data.clone();
+        bad_magic[0] ^= 0xff;
+        std::fs::write(&path, &bad_magic).unwrap();
+        assert_eq!(cache.0.load(1, &mut vec![]), None);
+
+        let mut bad_version = data.clone();
+        bad_version[4] = bad_version[4].wrapping_add(1);
+        std::fs::write(&path, &bad_version).unwrap();
+        assert_eq!(cache.0.load(1, &mut vec![]), None);
+
+        let mut bad_count = data;
+        bad_count[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(&path, &bad_count).unwrap();
+        assert_eq!(cache.0.load(1, &mut vec![]), None);
+    }
+}
@@ -4,26 +4,42 @@
 // TODO: move the manufactury into its own crate probably and generally be way smarter about this
 
 use crate::wgpu;
-use acidalia_core::Nametag;
+use acidalia_core::{register_name, register_tag, tag_name, Nametag, StableHasher, Tag, TagName};
 use acidalia_proc_macros::Nametag;
+use arc_swap::ArcSwap;
 use crossbeam_channel::Sender;
 use dashmap::DashMap;
+use futures::channel::oneshot;
 use notify::{
-    event::{AccessKind, AccessMode},
+    event::{AccessKind, AccessMode, ModifyKind},
     EventKind, RecommendedWatcher, Watcher,
 };
 use shaderc;
 use std::{
-    collections::hash_map::RandomState,
+    borrow::Cow,
+    cell::RefCell,
+    collections::{hash_map::RandomState, HashSet},
+    future::Future,
+    hash::Hasher,
     ops::Deref,
     path::Path,
-    sync::{Arc, RwLock, Weak},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock, Weak,
+    },
+    task::{Context, Poll},
     thread::JoinHandle,
+    time::{Duration, SystemTime},
 };
 use std::{num::NonZeroU32, path::PathBuf};
 use wgpu::{ComputePipeline, PipelineLayout, RenderPipeline, ShaderModule, ShaderModuleDescriptor};
 
 use crate::graphics::GraphicsState;
+use crate::shader_cache::ShaderCache;
+use crate::{EngineEvent, Error, EventProxy, VertexLayout};
+
+pub use crate::reflect::{ReflectedBinding, ReflectedLayout, ShaderReflection};
 
 #[derive(derive_more::From)]
 enum ManufacturingOutput {
@@ -45,35 +61,262 @@ impl ManufacturingOutput {
         }
         panic!("This isn't a compute pipeline")
     }
+}
 
-    unsafe fn swap(&self, val: &Arc<dyn Send + Sync>) {
-        match self {
-            ManufacturingOutput::RenderPipeline(v) => {
-                let ptr = (v as *const _) as *mut RenderPipeline;
-                std::ptr::swap(Arc::as_ptr(val) as *mut RenderPipeline, ptr)
-            }
-            ManufacturingOutput::ComputePipeline(v) => {
-                let ptr = (v as *const _) as *mut ComputePipeline;
-                std::ptr::swap(Arc::as_ptr(val) as *mut ComputePipeline, ptr)
-            }
+/// The kinds of pipelines that the manufactory can build.
+trait Manufactured: Send + Sync + Sized + 'static {
+    fn from_output(output: ManufacturingOutput) -> Self;
+}
+
+impl Manufactured for RenderPipeline {
+    fn from_output(output: ManufacturingOutput) -> Self {
+        output.render()
+    }
+}
+
+impl Manufactured for ComputePipeline {
+    fn from_output(output: ManufacturingOutput) -> Self {
+        output.compute()
+    }
+}
+
+/// Where the manufactory puts a rebuilt pipeline, without needing to know what kind it is.
+trait PipelineSlot: Send + Sync {
+    /// Whether anyone still has a handle to the pipeline.
+    fn is_alive(&self) -> bool;
+    fn store(&self, pipeline: ManufacturingOutput);
+    /// How many times the pipeline has been rebuilt, if anyone still has a handle to it.
+    fn generation(&self) -> Option<u64>;
+}
+
+impl<P: Manufactured> PipelineSlot for Weak<SharedPipeline<P>> {
+    fn is_alive(&self) -> bool {
+        self.strong_count() > 0
+    }
+
+    fn store(&self, pipeline: ManufacturingOutput) {
+        if let Some(slot) = self.upgrade() {
+            slot.pipeline.store(Arc::new(P::from_output(pipeline)));
+            slot.generation.fetch_add(1, Ordering::Release);
+        }
+    }
+
+    fn generation(&self) -> Option<u64> {
+        self.upgrade()
+            .map(|slot| slot.generation.load(Ordering::Acquire))
+    }
+}
+
+/// The part of a [`PipelineHandle`] that the manufactory writes to.
+struct SharedPipeline<P> {
+    pipeline: ArcSwap<P>,
+    generation: AtomicU64,
+    /// Tells the compiler thread when the pipeline is gone.
+    tx: Sender<CompilerMessage>,
+}
+
+impl<P> Drop for SharedPipeline<P> {
+    fn drop(&mut self) {
+        // The compiler thread may already have stopped, which is fine.
+        let _ = self.tx.send(CompilerMessage::PipelineDropped);
+    }
+}
+
+/// A pipeline built through the manufactory, which is replaced with a new one whenever
+/// its shaders are reloaded. Call [`PipelineHandle::get`] every frame to pick up the
+/// latest version.
+///
+/// Cloning the handle gives another handle to the same pipeline.
+pub struct PipelineHandle<P> {
+    shared: Arc<SharedPipeline<P>>,
+    current: Arc<P>,
+}
+
+impl<P> PipelineHandle<P> {
+    fn new(pipeline: P, tx: Sender<CompilerMessage>) -> Self {
+        let current = Arc::new(pipeline);
+        Self {
+            shared: Arc::new(SharedPipeline {
+                pipeline: ArcSwap::new(Arc::clone(&current)),
+                generation: AtomicU64::new(0),
+                tx,
+            }),
+            current,
+        }
+    }
+
+    /// Get the latest version of the pipeline. The previous version is dropped, but any
+    /// render pass that still uses it keeps it alive on the GPU side until it is done.
+    pub fn get(&mut self) -> &P {
+        let latest = self.shared.pipeline.load();
+        if !Arc::ptr_eq(&latest, &self.current) {
+            self.current = arc_swap::Guard::into_inner(latest);
+        }
+        &self.current
+    }
+
+    /// Get the latest version of the pipeline without updating this handle.
+    pub fn load(&self) -> Arc<P> {
+        self.shared.pipeline.load_full()
+    }
+
+    /// How many times the pipeline has been rebuilt since it was first built. This is
+    /// mostly useful for checking that a shader change was picked up.
+    pub fn generation(&self) -> u64 {
+        self.shared.generation.load(Ordering::Acquire)
+    }
+}
+
+impl<P> Clone for PipelineHandle<P> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: Arc::clone(&self.shared),
+            current: Arc::clone(&self.current),
         }
     }
 }
 
-type Manufacturer = Box<dyn Fn(&wgpu::Device, ShaderSet) -> ManufacturingOutput + Send + Sync>;
+/// Builds a pipeline from a set of shaders, given the current surface format. This fails if
+/// the pipeline's layout has to be generated and that can't be done.
+type Manufacturer = Box<
+    dyn Fn(&wgpu::Device, ShaderSet, wgpu::TextureFormat) -> Result<ManufacturingOutput, String>
+        + Send
+        + Sync,
+>;
 
 struct ManufacturingData {
     manufacturer: Manufacturer,
+    label: Option<String>,
     tags: ShaderTags,
-    pipeline: Weak<dyn Send + Sync>,
+    pipeline: Box<dyn PipelineSlot>,
+    /// Whether any of the pipeline's targets were marked as drawing to the surface, meaning
+    /// it has to be rebuilt when the surface format changes.
+    swapchain_bound: bool,
+    /// Why the last rebuild failed, if it did. The pipeline from before is kept in the
+    /// meantime.
+    error: Option<String>,
 }
 
 impl ManufacturingData {
-    fn new(manufacturer: Manufacturer, tags: ShaderTags, pipeline: Weak<dyn Send + Sync>) -> Self {
+    fn new(
+        manufacturer: Manufacturer,
+        label: Option<String>,
+        tags: ShaderTags,
+        pipeline: Box<dyn PipelineSlot>,
+        swapchain_bound: bool,
+    ) -> Self {
         Self {
             manufacturer,
+            label,
             tags,
             pipeline,
+            swapchain_bound,
+            error: None,
+        }
+    }
+}
+
+/// Rebuild every live pipeline that `filter` picks out, and hand the new pipelines to
+/// their handles. The surface format is read once the manufacturers are locked, so a
+/// concurrent format change can't be undone by a rebuild with the old format.
+fn rebuild_pipelines(
+    device: &wgpu::Device,
+    map: &ShaderMap,
+    manufacturers: &RwLock<Vec<ManufacturingData>>,
+    surface_format: &RwLock<wgpu::TextureFormat>,
+    filter: impl Fn(&ManufacturingData) -> bool,
+) {
+    let mut mfs = manufacturers.write().unwrap();
+    let surface_format = *surface_format.read().unwrap();
+    mfs.retain(|i: &ManufacturingData| i.pipeline.is_alive());
+    for data in mfs.iter_mut() {
+        if filter(data) {
+            let render_set = create_render_set(map, data.tags);
+            match (data.manufacturer)(device, render_set, surface_format) {
+                Ok(new_pipeline) => {
+                    data.pipeline.store(new_pipeline);
+                    data.error = None;
+                }
+                Err(e) => data.error = Some(e),
+            }
+        }
+    }
+}
+
+/// The language a shader is written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderLanguage {
+    /// GLSL, which is compiled to SPIR-V with `shaderc`.
+    Glsl,
+    /// WGSL, which is validated with `naga` and handed to `wgpu` as is.
+    Wgsl,
+}
+
+impl ShaderLanguage {
+    /// Guess the language from a file name: `.wgsl` files are WGSL, and everything
+    /// else is assumed to be GLSL.
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("wgsl") => ShaderLanguage::Wgsl,
+            _ => ShaderLanguage::Glsl,
+        }
+    }
+}
+
+/// Options for compiling a shader. Unlike [`shaderc::CompileOptions`], these are kept around
+/// with the shader, so they are applied again every time it hot-reloads.
+///
+/// WGSL has no preprocessor, so these only affect GLSL shaders.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShaderOptions {
+    defines: Vec<(String, Option<String>)>,
+    optimization: Option<shaderc::OptimizationLevel>,
+    target_env: Option<(shaderc::TargetEnv, u32)>,
+    debug_info: bool,
+}
+
+impl ShaderOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a preprocessor macro, as if by `#define name value`.
+    pub fn define(mut self, name: impl Into<String>, value: Option<&str>) -> Self {
+        self.defines
+            .push((name.into(), value.map(|v| v.to_owned())));
+        self
+    }
+
+    /// Set how hard the compiler tries to optimize the shader.
+    pub fn optimization(mut self, level: shaderc::OptimizationLevel) -> Self {
+        self.optimization = Some(level);
+        self
+    }
+
+    /// Set the environment the shader is compiled for.
+    pub fn target_env(mut self, env: shaderc::TargetEnv, version: shaderc::EnvVersion) -> Self {
+        self.target_env = Some((env, version as u32));
+        self
+    }
+
+    /// Set whether to include debug information in the compiled shader.
+    pub fn debug_info(mut self, debug_info: bool) -> Self {
+        self.debug_info = debug_info;
+        self
+    }
+
+    fn apply(&self, options: &mut shaderc::CompileOptions) {
+        for (name, value) in self.defines.iter() {
+            options.add_macro_definition(name, value.as_deref());
+        }
+        if let Some(level) = self.optimization {
+            options.set_optimization_level(level);
+        }
+        if let Some((env, version)) = self.target_env {
+            options.set_target_env(env, version);
+        }
+        if self.debug_info {
+            options.set_generate_debug_info();
         }
     }
 }
@@ -85,9 +328,69 @@ pub struct ShaderSourceDescriptor {
     data: Option<String>,
     entry_point: String,
     kind: shaderc::ShaderKind,
+    language: ShaderLanguage,
+    options: ShaderOptions,
 }
 
 impl ShaderSourceDescriptor {
+    fn file(
+        path: PathBuf,
+        entry_point: String,
+        kind: shaderc::ShaderKind,
+        language: ShaderLanguage,
+        options: Option<&ShaderOptions>,
+    ) -> Self {
+        Self {
+            path: Some(path),
+            filename: None,
+            data: None,
+            entry_point,
+            kind,
+            language,
+            options: options.cloned().unwrap_or_default(),
+        }
+    }
+
+    fn src(
+        filename: &str,
+        src: &str,
+        entry_point: &str,
+        kind: shaderc::ShaderKind,
+        language: ShaderLanguage,
+        options: Option<&ShaderOptions>,
+    ) -> Self {
+        Self {
+            path: None,
+            filename: Some(filename.to_owned()),
+            data: Some(src.to_owned()),
+            entry_point: entry_point.to_owned(),
+            kind,
+            language,
+            options: options.cloned().unwrap_or_default(),
+        }
+    }
+
+    /// The file the shader is loaded from, if it isn't from a string.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn entry_point(&self) -> &str {
+        &self.entry_point
+    }
+
+    pub fn kind(&self) -> shaderc::ShaderKind {
+        self.kind
+    }
+
+    pub fn language(&self) -> ShaderLanguage {
+        self.language
+    }
+
+    pub fn options(&self) -> &ShaderOptions {
+        &self.options
+    }
+
     pub fn filename(&self) -> String {
         match self.filename.as_ref() {
             Some(f) => f.clone(),
@@ -101,12 +404,362 @@ impl ShaderSourceDescriptor {
     }
 }
 
-type SMapRef<'a> = dashmap::mapref::one::Ref<
-    'a,
-    u128,
-    (Option<ShaderSourceDescriptor>, ShaderModule),
-    RandomState,
->;
+/// A single problem found while compiling a shader.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShaderError {
+    /// The file the error is in. This is the path of an include if the error is in one,
+    /// and the shader's file name otherwise.
+    pub file: String,
+    /// The line the error is on, starting from 1, if the compiler said.
+    pub line: Option<u32>,
+    /// The column the error is on, starting from 1, if the compiler said.
+    pub column: Option<u32>,
+    /// What the compiler had to say.
+    pub message: String,
+}
+
+impl ShaderError {
+    fn new(file: &str, message: impl Into<String>) -> Self {
+        Self {
+            file: file.to_owned(),
+            line: None,
+            column: None,
+            message: message.into(),
+        }
+    }
+
+    /// Parse the errors out of `shaderc`'s output.
+    fn from_shaderc(filename: &str, error: shaderc::Error) -> Vec<Self> {
+        match error {
+            shaderc::Error::CompilationError(_, output) => output
+                .lines()
+                .filter(|line| !line.trim().is_empty() && !line.ends_with("generated."))
+                .map(|line| Self::parse_shaderc_line(filename, line))
+                .collect(),
+            e => vec![Self::new(filename, e.to_string())],
+        }
+    }
+
+    /// Parse a line of the form `file:line: error: message`. The file name may have a
+    /// drive letter in it, so the line number is found by looking for the first field
+    /// that's only digits.
+    fn parse_shaderc_line(filename: &str, line: &str) -> Self {
+        let mut search = 0;
+        while let Some(start) = line[search..].find(':').map(|i| i + search) {
+            let rest = &line[start + 1..];
+            if let Some(end) = rest.find(':') {
+                if let Ok(line_number) = rest[..end].parse() {
+                    let message = rest[end + 1..].trim();
+                    let message = message.strip_prefix("error:").unwrap_or(message).trim();
+                    return Self {
+                        file: line[..start].to_owned(),
+                        line: Some(line_number),
+                        column: None,
+                        message: message.to_owned(),
+                    };
+                }
+            }
+            search = start + 1;
+        }
+        Self::new(filename, line.trim())
+    }
+}
+
+impl std::fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.file)?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        if let Some(column) = self.column {
+            write!(f, ":{}", column)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Whether a shader's last compile worked.
+#[derive(Clone, Debug)]
+pub enum ShaderStatus {
+    Compiled,
+    /// The shader failed to compile. If it had compiled before, the last working version is
+    /// still in use.
+    Failed(Vec<ShaderError>),
+}
+
+/// A shader known to a [`ShaderState`], as listed by [`ShaderState::shaders`].
+#[derive(Clone, Debug)]
+pub struct ShaderInfo {
+    /// Wrap this in a [`TagName`] to show the shader by name.
+    pub tag: Tag,
+    pub source: ShaderSourceDescriptor,
+    /// When the shader last finished compiling, whether it worked or not.
+    pub attempted_at: SystemTime,
+    /// When the version of the shader in use was compiled, or `None` if it never has.
+    /// Shaders loaded from the cache count as compiled when they were loaded.
+    pub compiled_at: Option<SystemTime>,
+    pub status: ShaderStatus,
+}
+
+/// A pipeline built through a [`ShaderState`], as listed by [`ShaderState::pipelines`].
+#[derive(Clone, Debug)]
+pub struct PipelineInfo {
+    pub label: Option<String>,
+    pub shaders: ShaderTags,
+    /// How many times the pipeline has been rebuilt, as in [`PipelineHandle::generation`].
+    pub generation: u64,
+    /// Whether the pipeline draws to the surface, and is rebuilt when its format changes.
+    pub surface_bound: bool,
+    /// Why the pipeline couldn't be rebuilt the last time its shaders changed, if it
+    /// couldn't. The pipeline from before that is still in use.
+    pub error: Option<String>,
+}
+
+/// A shader that's ready to be turned into a [`ShaderModule`].
+#[derive(Debug)]
+struct CompiledShader {
+    code: ShaderCode,
+    /// Reflection isn't needed to use the shader, so failing it only matters to pipelines
+    /// that want their layout generated.
+    reflection: Result<ShaderReflection, String>,
+}
+
+impl CompiledShader {
+    fn spirv(words: Vec<u32>, entry_point: &str) -> Self {
+        Self {
+            reflection: ShaderReflection::from_spirv(&words, entry_point),
+            code: ShaderCode::SpirV(words),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum ShaderCode {
+    SpirV(Vec<u32>),
+    Wgsl(String),
+}
+
+impl ShaderCode {
+    fn source(self) -> wgpu::ShaderSource<'static> {
+        match self {
+            ShaderCode::SpirV(data) => wgpu::ShaderSource::SpirV(data.into()),
+            ShaderCode::Wgsl(data) => wgpu::ShaderSource::Wgsl(data.into()),
+        }
+    }
+}
+
+type CompileResult = Result<CompiledShader, Vec<ShaderError>>;
+
+/// Compile the shader described by `src_desc`, reading it from disk if needed. GLSL
+/// shaders are loaded from the `cache` instead when they're in it, and saved to it when
+/// they're not. WGSL shaders are validated against the device `features`.
+fn compile_source(
+    compiler: &mut shaderc::Compiler,
+    src_desc: &ShaderSourceDescriptor,
+    include_dirs: &[PathBuf],
+    includes: &mut Vec<PathBuf>,
+    cache: Option<&ShaderCache>,
+    features: wgpu::Features,
+) -> CompileResult {
+    let data: Cow<str> = match (&src_desc.data, &src_desc.path) {
+        (Some(data), _) => data.into(),
+        (None, Some(path)) => std::fs::read_to_string(path)
+            .map_err(|e| {
+                let message = format!("Unable to read file: {}", e);
+                vec![ShaderError::new(&src_desc.filename(), message)]
+            })?
+            .into(),
+        (None, None) => unreachable!("Shader has no source"),
+    };
+    // Only GLSL goes through shaderc, so it's the only thing worth caching.
+    let cache = cache
+        .filter(|_| src_desc.language == ShaderLanguage::Glsl)
+        .map(|cache| (cache, cache_key(cache, &data, src_desc, include_dirs)));
+    if let Some((cache, key)) = cache {
+        if let Some(words) = cache.load(key, includes) {
+            return Ok(CompiledShader::spirv(words, &src_desc.entry_point));
+        }
+    }
+    let start = includes.len();
+    let res = compile(compiler, &data, src_desc, include_dirs, includes, features);
+    if let (Some((cache, key)), Ok(compiled)) = (cache, &res) {
+        if let ShaderCode::SpirV(words) = &compiled.code {
+            cache.store(key, &includes[start..], words);
+        }
+    }
+    res
+}
+
+/// Hash everything that decides what a shader compiles to, to find it in the cache.
+fn cache_key(
+    cache: &ShaderCache,
+    src: &str,
+    src_desc: &ShaderSourceDescriptor,
+    include_dirs: &[PathBuf],
+) -> u128 {
+    let mut hasher = StableHasher::new();
+    let mut write = |bytes: &[u8]| {
+        // Prefix everything with its length, so that moving bytes from one field to the
+        // next changes the key.
+        hasher.write(&(bytes.len() as u64).to_le_bytes());
+        hasher.write(bytes);
+    };
+    // The compiler can't be identified from here, so that's left to the salt. The engine
+    // version covers how the options are handed to it.
+    write(cache.salt.as_bytes());
+    write(env!("CARGO_PKG_VERSION").as_bytes());
+    write(src.as_bytes());
+    write(src_desc.filename().as_bytes());
+    if let Some(path) = &src_desc.path {
+        write(path.to_string_lossy().as_bytes());
+    }
+    write(src_desc.entry_point.as_bytes());
+    write(format!("{:?}", src_desc.kind).as_bytes());
+    write(format!("{:?}", src_desc.options).as_bytes());
+    for dir in include_dirs {
+        write(dir.to_string_lossy().as_bytes());
+    }
+    hasher.finish128()
+}
+
+/// Turn the source of a shader into something `wgpu` can build a module from. Every file
+/// pulled in with `#include` is added to `includes`, even if compilation fails.
+fn compile(
+    compiler: &mut shaderc::Compiler,
+    src: &str,
+    src_desc: &ShaderSourceDescriptor,
+    include_dirs: &[PathBuf],
+    includes: &mut Vec<PathBuf>,
+    features: wgpu::Features,
+) -> CompileResult {
+    let filename = src_desc.filename();
+    match src_desc.language {
+        ShaderLanguage::Glsl => {
+            let found = RefCell::new(vec![]);
+            let mut options = shaderc::CompileOptions::new().ok_or_else(|| {
+                vec![ShaderError::new(
+                    &filename,
+                    "Unable to create compile options",
+                )]
+            })?;
+            src_desc.options.apply(&mut options);
+            let base = src_desc.path.as_ref().and_then(|path| path.parent());
+            options.set_include_callback(|name, include_type, requester, _depth| {
+                let (path, content) =
+                    resolve_include(name, include_type, requester, base, include_dirs)?;
+                let resolved_name = path.to_string_lossy().into_owned();
+                found.borrow_mut().push(path);
+                Ok(shaderc::ResolvedInclude {
+                    resolved_name,
+                    content,
+                })
+            });
+            let res = compiler
+                .compile_into_spirv(
+                    src,
+                    src_desc.kind,
+                    &filename,
+                    &src_desc.entry_point,
+                    Some(&options),
+                )
+                .map(|res| CompiledShader::spirv(res.as_binary().to_vec(), &src_desc.entry_point))
+                .map_err(|e| ShaderError::from_shaderc(&filename, e));
+            drop(options);
+            includes.extend(found.into_inner());
+            res
+        }
+        ShaderLanguage::Wgsl => {
+            // wgpu would panic on an invalid module, so check it here first where the
+            // error can be reported instead.
+            let module = naga::front::wgsl::parse_str(src).map_err(|e| {
+                let (line, column) = e.location(src);
+                vec![ShaderError {
+                    file: filename.clone(),
+                    line: Some(line as u32),
+                    column: Some(column as u32),
+                    message: e.to_string(),
+                }]
+            })?;
+            let info = naga::valid::Validator::new(
+                naga::valid::ValidationFlags::all(),
+                validation_capabilities(features),
+            )
+            .validate(&module)
+            .map_err(|e| vec![ShaderError::new(&filename, e.to_string())])?;
+            if !module
+                .entry_points
+                .iter()
+                .any(|ep| ep.name == src_desc.entry_point)
+            {
+                let message = format!("No entry point named '{}'", src_desc.entry_point);
+                return Err(vec![ShaderError::new(&filename, message)]);
+            }
+            Ok(CompiledShader {
+                reflection: ShaderReflection::from_module(&module, &info, &src_desc.entry_point),
+                code: ShaderCode::Wgsl(src.to_owned()),
+            })
+        }
+    }
+}
+
+/// The capabilities that `wgpu` validates shaders with on a device with the given `features`.
+/// Validating with the same ones means that a module that passes here won't make `wgpu`
+/// panic when it's created.
+fn validation_capabilities(features: wgpu::Features) -> naga::valid::Capabilities {
+    use naga::valid::Capabilities;
+    let mut capabilities = Capabilities::empty();
+    capabilities.set(
+        Capabilities::PUSH_CONSTANT,
+        features.contains(wgpu::Features::PUSH_CONSTANTS),
+    );
+    capabilities.set(
+        Capabilities::FLOAT64,
+        features.contains(wgpu::Features::SHADER_FLOAT64),
+    );
+    capabilities.set(
+        Capabilities::PRIMITIVE_INDEX,
+        features.contains(wgpu::Features::SHADER_PRIMITIVE_INDEX),
+    );
+    capabilities
+}
+
+/// Find the file that an `#include` directive refers to, returning its canonical path and
+/// contents.
+///
+/// `#include "..."` is resolved relative to the file containing it, and `#include <...>`
+/// against the include directories. If a relative include can't be found, shaderc tries
+/// again as a standard one, so quoted includes fall back on the include directories too.
+fn resolve_include(
+    name: &str,
+    include_type: shaderc::IncludeType,
+    requester: &str,
+    base: Option<&Path>,
+    include_dirs: &[PathBuf],
+) -> Result<(PathBuf, String), String> {
+    let candidates: Vec<PathBuf> = match include_type {
+        shaderc::IncludeType::Relative => {
+            // Nested includes are requested by the canonical path we resolved them to, while
+            // the shader itself is only known by its file name.
+            let requester = Path::new(requester);
+            let dir = match requester.is_absolute() {
+                true => requester.parent(),
+                false => base,
+            };
+            dir.map(|dir| dir.join(name)).into_iter().collect()
+        }
+        shaderc::IncludeType::Standard => include_dirs.iter().map(|dir| dir.join(name)).collect(),
+    };
+    candidates
+        .into_iter()
+        .find_map(|path| {
+            let path = std::fs::canonicalize(path).ok()?;
+            let content = std::fs::read_to_string(&path).ok()?;
+            Some((path, content))
+        })
+        .ok_or_else(|| format!("Unable to find include '{}'", name))
+}
+
+type SMapRef<'a> = dashmap::mapref::one::Ref<'a, u128, ShaderEntry, RandomState>;
 
 pub struct ShaderRef<'a>(SMapRef<'a>);
 
@@ -118,33 +771,288 @@ impl<'a> Deref for ShaderRef<'a> {
     }
 }
 
+impl<'a> ShaderRef<'a> {
+    /// The resources this shader expects to be bound, or why they couldn't be worked out.
+    pub fn reflection(&self) -> Result<&ShaderReflection, &str> {
+        self.0 .2.as_ref().map_err(|e| e.as_str())
+    }
+}
+
 impl<'a> From<SMapRef<'a>> for ShaderRef<'a> {
     fn from(val: SMapRef<'a>) -> Self {
         Self(val)
     }
 }
 
+/// How long the file watcher waits after the last change to a file before acting on it.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// The file watcher, along with everything it has been told to watch.
+struct Watches {
+    watcher: RecommendedWatcher,
+    dirs: HashSet<PathBuf>,
+    trees: Vec<PathBuf>,
+}
+
+impl Watches {
+    /// Watch a file for changes. This watches the directory it is in rather than the file
+    /// itself, since editors that save by renaming a new file over the old one would leave
+    /// a watch on the file pointing at the deleted original.
+    fn watch_file(&mut self, path: &Path) -> notify::Result<()> {
+        match path.parent() {
+            Some(dir) => self.watch_dir(dir),
+            None => Ok(()),
+        }
+    }
+
+    /// Watch the files directly inside a directory.
+    fn watch_dir(&mut self, dir: &Path) -> notify::Result<()> {
+        if self.dirs.contains(dir) || self.trees.iter().any(|tree| dir.starts_with(tree)) {
+            return Ok(());
+        }
+        self.watcher
+            .watch(dir, notify::RecursiveMode::NonRecursive)?;
+        self.dirs.insert(dir.to_owned());
+        Ok(())
+    }
+
+    /// Watch a directory and everything in it.
+    fn watch_tree(&mut self, dir: &Path) -> notify::Result<()> {
+        if self.trees.iter().any(|tree| dir.starts_with(tree)) {
+            return Ok(());
+        }
+        self.watcher.watch(dir, notify::RecursiveMode::Recursive)?;
+        // Directories inside the tree share its watch now, so they mustn't be unwatched on
+        // their own.
+        self.dirs.retain(|watched| !watched.starts_with(dir));
+        self.trees.push(dir.to_owned());
+        Ok(())
+    }
+
+    /// Stop watching a directory that was watched with [`Watches::watch_tree`].
+    fn unwatch_tree(&mut self, dir: &Path) -> notify::Result<()> {
+        if let Some(i) = self.trees.iter().position(|tree| tree == dir) {
+            self.trees.remove(i);
+            self.watcher.unwatch(dir)?;
+        }
+        Ok(())
+    }
+
+    /// Watch the directory of each of `files`, and stop watching every other directory
+    /// that was only watched for the files in it.
+    fn retain_files(&mut self, files: &[PathBuf]) -> notify::Result<()> {
+        let needed: HashSet<&Path> = files.iter().filter_map(|file| file.parent()).collect();
+        let unused: Vec<PathBuf> = self
+            .dirs
+            .iter()
+            .filter(|dir| !needed.contains(dir.as_path()))
+            .cloned()
+            .collect();
+        for dir in unused {
+            self.dirs.remove(&dir);
+            // Deleting a directory takes its watch with it, so this is allowed to fail.
+            let _ = self.watcher.unwatch(&dir);
+        }
+        for dir in needed {
+            self.watch_dir(dir)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every file that the known shaders were built from, including their includes. Shaders that
+/// failed to compile are counted too, so fixing them reloads them.
+fn watched_files(log: &CompileLog, include_map: &IncludeMap) -> Vec<PathBuf> {
+    let sources = log
+        .iter()
+        .filter_map(|entry| entry.value().source.path.clone());
+    let includes = include_map.iter().flat_map(|entry| entry.value().clone());
+    sources.chain(includes).collect()
+}
+
+/// Picks the tag and entry point for a file in a watched directory, given its path relative
+/// to the directory.
+type Naming = Box<dyn Fn(&Path) -> Option<(Tag, String)> + Send + Sync>;
+
+/// A directory registered with [`ShaderState::watch_dir`].
+struct WatchedDir {
+    root: PathBuf,
+    naming: Naming,
+}
+
+impl WatchedDir {
+    /// Work out what to load the file at `path` as, if it's a shader in this directory that
+    /// the naming function wants.
+    fn shader(&self, path: &Path) -> Option<(u128, ShaderSourceDescriptor)> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        let kind = shader_kind_from_path(path)?;
+        let (tag, entry_point) = (self.naming)(relative)?;
+        if tag_name(tag).is_none() {
+            register_name(tag, relative.to_string_lossy());
+        }
+        let src_desc = ShaderSourceDescriptor::file(
+            path.to_owned(),
+            entry_point,
+            kind,
+            ShaderLanguage::from_path(path),
+            None,
+        );
+        Some((tag, src_desc))
+    }
+}
+
+/// Guess the stage of a shader from its file extension, using the same names as
+/// `glslangValidator`. WGSL files say which stage they are in the source.
+fn shader_kind_from_path(path: &Path) -> Option<shaderc::ShaderKind> {
+    match path.extension()?.to_str()? {
+        "vert" => Some(shaderc::ShaderKind::Vertex),
+        "frag" => Some(shaderc::ShaderKind::Fragment),
+        "comp" => Some(shaderc::ShaderKind::Compute),
+        "geom" => Some(shaderc::ShaderKind::Geometry),
+        "tesc" => Some(shaderc::ShaderKind::TessControl),
+        "tese" => Some(shaderc::ShaderKind::TessEvaluation),
+        "wgsl" => Some(shaderc::ShaderKind::InferFromSource),
+        _ => None,
+    }
+}
+
+/// Collect every file in `dir` and its subdirectories into `files`. Symlinks to files are
+/// followed, but symlinks to directories aren't, since they could loop back on themselves.
+fn walk_dir(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            walk_dir(&path, files)?;
+        } else if path.is_file() {
+            files.push(std::fs::canonicalize(path)?);
+        }
+    }
+    Ok(())
+}
+
+/// React to files that the watcher saw change. Shaders that have been compiled before,
+/// whether that worked or not, are recompiled if they or any of their includes changed, or
+/// unloaded if they came from a watched directory and are gone. New shaders in watched
+/// directories are loaded.
+fn apply_changes(
+    log: &CompileLog,
+    include_map: &IncludeMap,
+    watched_dirs: &[WatchedDir],
+    tx: &Sender<CompilerMessage>,
+    changed: &HashSet<PathBuf>,
+) {
+    let mut known = HashSet::new();
+    for entry in log.iter() {
+        let key = *entry.key();
+        let src_desc = &entry.value().source;
+        if let Some(path) = &src_desc.path {
+            known.insert(path.clone());
+            if changed.contains(path)
+                && !path.exists()
+                && watched_dirs.iter().any(|dir| path.starts_with(&dir.root))
+            {
+                tx.send(CompilerMessage::Unload(key, None)).unwrap();
+                continue;
+            }
+        }
+        let affected = src_desc
+            .path
+            .as_ref()
+            .is_some_and(|path| changed.contains(path))
+            || include_map
+                .get(&key)
+                .is_some_and(|inc| inc.iter().any(|path| changed.contains(path)));
+        if !affected {
+            continue;
+        }
+        let msg = match src_desc.path {
+            Some(_) => CompilerMessage::FromFile(key, src_desc.clone(), None),
+            None => CompilerMessage::FromString(key, src_desc.clone(), None),
+        };
+        tx.send(msg).unwrap();
+    }
+    for path in changed
+        .iter()
+        .filter(|path| !known.contains(*path) && path.is_file())
+    {
+        if let Some((key, src_desc)) = watched_dirs.iter().find_map(|dir| dir.shader(path)) {
+            tx.send(CompilerMessage::FromFile(key, src_desc, None))
+                .unwrap();
+        }
+    }
+}
+
+/// Tells whoever asked for a shader how compiling it went.
+type CompileDone = oneshot::Sender<Result<(), Vec<ShaderError>>>;
+
 #[derive(Debug)]
 enum CompilerMessage {
-    FromFile(u128, ShaderSourceDescriptor),
-    FromString(u128, ShaderSourceDescriptor),
-    CullRefs,
-    #[allow(dead_code)]
-    Interrupt, // TODO: think about if i really need this
+    FromFile(u128, ShaderSourceDescriptor, Option<CompileDone>),
+    FromString(u128, ShaderSourceDescriptor, Option<CompileDone>),
+    /// A shader that was already compiled on another thread, and just needs to be loaded.
+    Compiled {
+        key: u128,
+        src_desc: ShaderSourceDescriptor,
+        result: CompileResult,
+        includes: Vec<PathBuf>,
+        done: Option<CompileDone>,
+    },
+    /// Forget about a shader, replying with whether it was unloaded. A shader that a
+    /// pipeline still uses is unloaded once that pipeline is dropped instead.
+    Unload(u128, Option<oneshot::Sender<bool>>),
+    /// The last handle to a pipeline was dropped, so its manufacturer can be thrown away and
+    /// shaders waiting on it can be unloaded.
+    PipelineDropped,
+    /// Stop the compiler thread, once everything queued before this has been dealt with.
+    Interrupt,
 }
 
-type ShaderMap = DashMap<u128, (Option<ShaderSourceDescriptor>, ShaderModule)>;
+/// A loaded shader: where it came from (if it can be reloaded), its module, and what its
+/// reflection found.
+type ShaderEntry = (
+    Option<ShaderSourceDescriptor>,
+    ShaderModule,
+    Result<ShaderReflection, String>,
+);
+
+type ShaderMap = DashMap<u128, ShaderEntry>;
+
+/// When each shader was last compiled, and where it came from.
+type CompileLog = DashMap<u128, CompileRecord>;
+
+struct CompileRecord {
+    source: ShaderSourceDescriptor,
+    /// When the shader last finished compiling, whether it worked or not.
+    attempted_at: SystemTime,
+    /// When the version of the shader in use was compiled.
+    compiled_at: Option<SystemTime>,
+}
+
+/// Shaders that failed their last compile, along with what went wrong.
+type FailureMap = DashMap<u128, Vec<ShaderError>>;
+
+/// Every file that each shader pulled in with `#include`, directly or not.
+type IncludeMap = DashMap<u128, Vec<PathBuf>>;
 
 fn get_shader_ref(map: &ShaderMap, key: impl Nametag) -> Option<ShaderRef> {
     map.get(&key.tag()).map(|i| i.into())
 }
 
+/// Like [`get_shader_ref`], but failing with an error that names the missing shader.
+fn loaded_shader(map: &ShaderMap, tag: Tag) -> Result<ShaderRef<'_>, Error> {
+    get_shader_ref(map, tag).ok_or(Error::MissingShader(TagName(tag)))
+}
+
 const LABELS: &'static [&'static str] = &["vertex", "fragment", "compute"];
 
 fn create_render_set(map: &ShaderMap, tags: ShaderTags) -> ShaderSet {
     let tags = [tags.vertex, tags.fragment, tags.compute];
     let mut map = tags.iter().zip(LABELS.iter()).map(|(t, l)| {
-        t.map(|i| get_shader_ref(map, i).expect(&format!("No shader registered with {} tag", l)))
+        t.map(|i| {
+            get_shader_ref(map, i)
+                .unwrap_or_else(|| panic!("No {} shader registered as {}", l, TagName(i)))
+        })
     });
     ShaderSet {
         vertex: map.next().unwrap(),
@@ -157,137 +1065,258 @@ fn create_render_set(map: &ShaderMap, tags: ShaderTags) -> ShaderSet {
 /// Utilizes `shaderc` to compile GLSL source into SPIR-V.
 pub struct ShaderState {
     shader_map: Arc<ShaderMap>,
+    failures: Arc<FailureMap>,
+    compile_log: Arc<CompileLog>,
+    include_map: Arc<IncludeMap>,
+    include_dirs: Arc<RwLock<Vec<PathBuf>>>,
+    cache: Arc<RwLock<Option<ShaderCache>>>,
     manufacturers: Arc<RwLock<Vec<ManufacturingData>>>,
-    watcher: RecommendedWatcher,
-    _handle: JoinHandle<()>,
+    watches: Arc<Mutex<Watches>>,
+    watched_dirs: Arc<RwLock<Vec<WatchedDir>>>,
+    handle: Option<JoinHandle<()>>,
     tx: Sender<CompilerMessage>,
     device: Arc<wgpu::Device>,
+    surface_format: Arc<RwLock<wgpu::TextureFormat>>,
 }
 
 impl ShaderState {
-    /// Construct a new `ShaderState`.
-    pub fn new(gs: &GraphicsState) -> Self {
+    /// Construct a new `ShaderState`. Whenever a shader is reloaded, an
+    /// [`EngineEvent::ShaderReloaded`] is sent through `proxy`.
+    pub fn new(gs: &GraphicsState, proxy: EventProxy) -> Result<Self, Error> {
         let manufacturers = Arc::new(RwLock::new(vec![]));
         let mfptr = Arc::clone(&manufacturers);
         let map: ShaderMap = ShaderMap::new();
         let shader_map = Arc::new(map);
-        let sm = Arc::clone(&shader_map);
+        let failures = Arc::new(FailureMap::new());
+        let fails = Arc::clone(&failures);
+        let compile_log = Arc::new(CompileLog::new());
+        let log = Arc::clone(&compile_log);
+        let attempted = Arc::clone(&compile_log);
+        let surface_format = Arc::new(RwLock::new(gs.swapchain_descriptor.format));
+        let format = Arc::clone(&surface_format);
+        let include_dirs = Arc::new(RwLock::new(vec![]));
+        let dirs = Arc::clone(&include_dirs);
+        let cache = Arc::new(RwLock::new(None));
+        let shader_cache = Arc::clone(&cache);
+        let include_map = Arc::new(IncludeMap::new());
+        let includes = Arc::clone(&include_map);
+        let inc = Arc::clone(&include_map);
         let (tx, rx) = crossbeam_channel::unbounded::<CompilerMessage>();
-        let tx2 = tx.clone();
-        let watcher: RecommendedWatcher =
-            notify::recommended_watcher(move |ev: Result<notify::Event, notify::Error>| match ev {
+        let watched_dirs = Arc::new(RwLock::new(vec![]));
+        let wdirs = Arc::clone(&watched_dirs);
+        let (change_tx, change_rx) = crossbeam_channel::unbounded();
+        let watcher: RecommendedWatcher = notify::recommended_watcher(
+            move |ev: Result<notify::Event, notify::Error>| match ev {
                 Ok(event) => {
                     // TODO: log
                     // println!("{:?}", event);
-                    if event.kind != EventKind::Access(AccessKind::Close(AccessMode::Write)) {
-                        return;
-                    }
-                    for mut entry in sm.iter_mut() {
-                        let key = *entry.key();
-                        if let Some(src_desc) = &entry.value_mut().0 {
-                            if let Some(path) = &src_desc.path {
-                                let path = std::fs::canonicalize(path).unwrap();
-                                if event
-                                    .paths
-                                    .iter()
-                                    .map(|f| std::fs::canonicalize(f).unwrap())
-                                    .collect::<Vec<_>>()
-                                    .contains(&path)
-                                {
-                                    tx2.send(CompilerMessage::FromFile(key, src_desc.clone()))
-                                        .unwrap();
-                                }
-                            }
+                    let relevant = matches!(
+                        event.kind,
+                        EventKind::Create(_)
+                            | EventKind::Remove(_)
+                            | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_))
+                            | EventKind::Modify(ModifyKind::Any)
+                            | EventKind::Access(AccessKind::Close(AccessMode::Write))
+                    );
+                    if relevant {
+                        for path in event.paths {
+                            let _ = change_tx.send(path);
                         }
                     }
                 }
                 Err(e) => println!("Watch error: {:?}", e),
-            })
-            .unwrap();
+            },
+        )?;
+        let tx2 = tx.clone();
+        std::thread::spawn(move || {
+            // This stops once the watcher is dropped, along with the sender it holds.
+            while let Ok(path) = change_rx.recv() {
+                let mut changed = HashSet::new();
+                changed.insert(path);
+                // Editors often save by writing a few files and renaming them over each
+                // other, so wait for things to settle down before looking at any of them.
+                while let Ok(path) = change_rx.recv_timeout(WATCH_DEBOUNCE) {
+                    changed.insert(path);
+                }
+                let changed = changed
+                    .into_iter()
+                    .map(|path| std::fs::canonicalize(&path).unwrap_or(path))
+                    .collect();
+                apply_changes(&attempted, &inc, &wdirs.read().unwrap(), &tx2, &changed);
+            }
+        });
+        let watches = Watches {
+            watcher,
+            dirs: HashSet::new(),
+            trees: vec![],
+        };
+        let watches = Arc::new(Mutex::new(watches));
+        let watch = Arc::clone(&watches);
         let sm = Arc::clone(&shader_map);
-        // TODO: remove the ManuallyDrop when gfx-rs/wgpu-rs#837 gets dealt with
-        let device = std::mem::ManuallyDrop::new(Arc::clone(&gs.device));
-        let _handle = std::thread::spawn(move || {
-            let mut compiler = shaderc::Compiler::new().unwrap();
-            let mut garbage = vec![];
+        let device = Arc::clone(&gs.device);
+        let (ready_tx, ready_rx) = crossbeam_channel::bounded(1);
+        let handle = std::thread::spawn(move || {
+            let mut compiler = match shaderc::Compiler::new() {
+                Some(compiler) => compiler,
+                None => {
+                    let _ = ready_tx.send(false);
+                    return;
+                }
+            };
+            let _ = ready_tx.send(true);
+            // Pipelines need their shaders to be rebuilt, so a shader that's still in use
+            // waits here until the last of its pipelines is dropped.
+            let mut pending_unloads = HashSet::new();
+            let in_use = |key: u128| {
+                let mut mfs = mfptr.write().unwrap();
+                mfs.retain(|data: &ManufacturingData| data.pipeline.is_alive());
+                mfs.iter().any(|data| data.tags.has_tag(key))
+            };
+            let unload = |key: u128| {
+                if log.remove(&key).is_none() {
+                    return false;
+                }
+                sm.remove(&key);
+                fails.remove(&key);
+                includes.remove(&key);
+                let files = watched_files(&log, &includes);
+                if let Err(e) = watch.lock().unwrap().retain_files(&files) {
+                    eprintln!("Unable to update watches: {}", e);
+                }
+                true
+            };
             'yeet: loop {
                 let val = rx.recv();
                 match val {
                     Ok(msg) => {
                         let key: u128;
-                        let source_descriptor: Option<ShaderSourceDescriptor>;
-                        let res: Result<shaderc::CompilationArtifact, shaderc::Error>;
-                        let filename: String;
+                        let source_descriptor: ShaderSourceDescriptor;
+                        let res: CompileResult;
+                        let mut found_includes = vec![];
+                        let done: Option<CompileDone>;
                         match msg {
-                            CompilerMessage::FromFile(key_, src_desc) => {
+                            CompilerMessage::FromFile(key_, src_desc, done_)
+                            | CompilerMessage::FromString(key_, src_desc, done_) => {
                                 key = key_;
-                                filename = src_desc.filename();
-                                let data =
-                                    std::fs::read_to_string(&src_desc.path.as_ref().unwrap())
-                                        .expect(&format!("Unable to read from {}!", filename));
-                                res = compiler.compile_into_spirv(
-                                    &data,
-                                    src_desc.kind,
-                                    &src_desc.filename(),
-                                    &src_desc.entry_point,
-                                    None,
+                                let include_dirs = dirs.read().unwrap().clone();
+                                let cache = shader_cache.read().unwrap().clone();
+                                res = compile_source(
+                                    &mut compiler,
+                                    &src_desc,
+                                    &include_dirs,
+                                    &mut found_includes,
+                                    cache.as_ref(),
+                                    device.features(),
                                 );
-                                source_descriptor = Some(src_desc);
+                                source_descriptor = src_desc;
+                                done = done_;
                             }
-                            CompilerMessage::FromString(key_, src_desc) => {
+                            CompilerMessage::Compiled {
+                                key: key_,
+                                src_desc,
+                                result,
+                                includes,
+                                done: done_,
+                            } => {
                                 key = key_;
-                                filename = src_desc.filename();
-                                res = compiler.compile_into_spirv(
-                                    &src_desc.data.as_ref().unwrap(),
-                                    src_desc.kind,
-                                    &src_desc.filename(),
-                                    &src_desc.entry_point,
-                                    None,
-                                );
-                                source_descriptor = Some(src_desc);
+                                res = result;
+                                found_includes = includes;
+                                source_descriptor = src_desc;
+                                done = done_;
+                            }
+                            CompilerMessage::Unload(key, done) => {
+                                let unloaded = match in_use(key) {
+                                    true => {
+                                        if log.contains_key(&key) {
+                                            pending_unloads.insert(key);
+                                        }
+                                        false
+                                    }
+                                    false => unload(key),
+                                };
+                                if let Some(done) = done {
+                                    let _ = done.send(unloaded);
+                                }
+                                continue 'yeet;
                             }
-                            CompilerMessage::CullRefs => {
-                                garbage.clear();
+                            CompilerMessage::PipelineDropped => {
+                                // Let go of the manufacturer straight away, along with the
+                                // layout it holds on to.
+                                mfptr
+                                    .write()
+                                    .unwrap()
+                                    .retain(|data| data.pipeline.is_alive());
+                                pending_unloads.retain(|&key| {
+                                    let keep = in_use(key);
+                                    if !keep {
+                                        unload(key);
+                                    }
+                                    keep
+                                });
                                 continue 'yeet;
                             }
                             CompilerMessage::Interrupt => {
                                 break 'yeet;
                             }
                         }
-                        match res {
-                            Ok(res) => {
+                        // Loading a shader again calls off an unload that was waiting on it.
+                        pending_unloads.remove(&key);
+                        // Keep an eye on the includes, so that editing one recompiles
+                        // every shader that uses it.
+                        for path in found_includes.iter() {
+                            if let Err(e) = watch.lock().unwrap().watch_file(path) {
+                                eprintln!("Unable to watch {}: {}", path.display(), e);
+                            }
+                        }
+                        includes.insert(key, found_includes);
+                        let now = SystemTime::now();
+                        let compiled_at = match &res {
+                            Ok(_) => Some(now),
+                            Err(_) => log.get(&key).and_then(|record| record.compiled_at),
+                        };
+                        log.insert(
+                            key,
+                            CompileRecord {
+                                source: source_descriptor.clone(),
+                                attempted_at: now,
+                                compiled_at,
+                            },
+                        );
+                        let status = match res {
+                            Ok(compiled) => {
+                                let label = tag_name(key);
                                 let desc = ShaderModuleDescriptor {
-                                    label: None,
-                                    source: wgpu::ShaderSource::SpirV(res.as_binary().into()),
+                                    label: label.as_deref(),
+                                    source: compiled.code.source(),
                                 };
-                                sm.insert(
-                                    key,
-                                    (source_descriptor, device.create_shader_module(&desc)),
-                                );
+                                let module = device.create_shader_module(&desc);
+                                let reloaded = sm
+                                    .insert(
+                                        key,
+                                        (Some(source_descriptor), module, compiled.reflection),
+                                    )
+                                    .is_some();
+                                fails.remove(&key);
+                                rebuild_pipelines(&device, &sm, &mfptr, &format, |data| {
+                                    data.tags.has_tag(key)
+                                });
+                                if reloaded {
+                                    // Nobody is listening once the engine has stopped, which is fine.
+                                    let _ = proxy.send(EngineEvent::ShaderReloaded(key));
+                                }
                                 // TODO: log
                                 // println!("Compiled {}", filename);
+                                Ok(())
                             }
-                            Err(e) => {
-                                eprintln!("Failed to recompile '{}': {}", filename, e);
-                                continue;
-                            }
-                        }
-                        let mut mfs = mfptr.write().unwrap();
-                        mfs.retain(|i: &ManufacturingData| i.pipeline.clone().upgrade().is_some());
-                        for data in mfs.iter() {
-                            if data.tags.has_tag(key) {
-                                let render_set = create_render_set(&sm, data.tags);
-                                let new_pipeline = (data.manufacturer)(&device, render_set);
-
-                                if let Some(pipe_ref) = data.pipeline.clone().upgrade() {
-                                    // TODO: this is probably way too much premature optimization, and I should rethink my
-                                    // design soon. maybe just switch to an ecs or something similar?
-                                    unsafe {
-                                        new_pipeline.swap(&pipe_ref);
-                                    }
-                                    garbage.push(new_pipeline);
-                                }
+                            Err(errors) => {
+                                fails.insert(key, errors.clone());
+                                let _ = proxy.send(EngineEvent::ShaderFailed(key, errors.clone()));
+                                Err(errors)
                             }
+                        };
+                        if let Some(done) = done {
+                            // The caller may have stopped waiting, which is fine.
+                            let _ = done.send(status);
                         }
                     }
                     Err(_) => {
@@ -297,52 +1326,124 @@ impl ShaderState {
             }
         });
 
-        Self {
+        if !ready_rx.recv().unwrap_or(false) {
+            return Err(Error::ShaderCompiler);
+        }
+
+        Ok(Self {
             shader_map,
+            failures,
+            compile_log,
+            include_map,
+            include_dirs,
+            cache,
             manufacturers,
-            watcher,
-            _handle,
+            watches,
+            watched_dirs,
+            handle: Some(handle),
             tx,
             device: Arc::clone(&gs.device),
+            surface_format,
+        })
+    }
+
+    /// Create a listener for [`GraphicsState::on_format_change`] that rebuilds all of the
+    /// pipelines that target the surface, before the format change returns.
+    pub(crate) fn format_listener(&self) -> impl Fn(wgpu::TextureFormat) {
+        let device = Arc::clone(&self.device);
+        let shader_map = Arc::clone(&self.shader_map);
+        let manufacturers = Arc::clone(&self.manufacturers);
+        let surface_format = Arc::clone(&self.surface_format);
+        move |format| {
+            *surface_format.write().unwrap() = format;
+            rebuild_pipelines(
+                &device,
+                &shader_map,
+                &manufacturers,
+                &surface_format,
+                |data| data.swapchain_bound,
+            );
         }
     }
 
-    /// Loads a shader from a file. Shaders added from here will hot-reload.
+    /// Loads a shader from a file, blocking until it has compiled. Shaders added from here
+    /// will hot-reload.
+    ///
+    /// The language is picked from the file extension with [`ShaderLanguage::from_path`].
     pub fn load_file(
         &mut self,
         key: impl Nametag,
         path: impl AsRef<Path>,
         entry_point: impl Into<String>,
         kind: shaderc::ShaderKind,
-        _options: Option<&shaderc::CompileOptions>,
-    ) {
-        let path = Some(path.as_ref().to_owned());
-        let entry_point: String = entry_point.into();
-        let src_desc = ShaderSourceDescriptor {
-            path,
-            entry_point,
-            kind,
-            filename: None,
-            data: None,
-        };
-        self.watcher
-            .watch(
-                &src_desc.path.as_ref().unwrap(),
-                notify::RecursiveMode::NonRecursive,
-            )
-            .unwrap();
-
-        let tag = key.tag();
-        self.tx
-            .send(CompilerMessage::FromFile(tag, src_desc))
-            .unwrap();
+        options: Option<&ShaderOptions>,
+    ) -> Result<(), Error> {
+        self.load_file_async(key, path, entry_point, kind, options)
+            .wait()
+    }
 
-        while !self.shader_map.contains_key(&tag) {
-            continue;
+    /// Loads a shader from a file without waiting for it to compile. Shaders added from
+    /// here will hot-reload.
+    ///
+    /// The language is picked from the file extension with [`ShaderLanguage::from_path`].
+    pub fn load_file_async(
+        &mut self,
+        key: impl Nametag,
+        path: impl AsRef<Path>,
+        entry_point: impl Into<String>,
+        kind: shaderc::ShaderKind,
+        options: Option<&ShaderOptions>,
+    ) -> PendingShader {
+        let language = ShaderLanguage::from_path(&path);
+        match std::fs::canonicalize(path) {
+            Ok(path) => {
+                let src_desc =
+                    ShaderSourceDescriptor::file(path, entry_point.into(), kind, language, options);
+                self.queue(register_tag(key), src_desc)
+            }
+            Err(e) => PendingShader::failed(e.into()),
         }
     }
 
-    /// Loads a shader from an `&str` source string.
+    /// Loads several variants of the same shader file, each under its own tag and compiled
+    /// with its own options. This is mostly useful for compiling the same shader with
+    /// different sets of `#define`s. All of the variants will hot-reload.
+    pub fn load_file_variants<K: Nametag>(
+        &mut self,
+        path: impl AsRef<Path>,
+        entry_point: &str,
+        kind: shaderc::ShaderKind,
+        variants: impl IntoIterator<Item = (K, ShaderOptions)>,
+    ) -> Result<(), Error> {
+        let batch = variants
+            .into_iter()
+            .fold(ShaderBatch::new(), |batch, (key, options)| {
+                batch.file(key, path.as_ref(), entry_point, kind, Some(&options))
+            });
+        self.load_batch(batch)
+    }
+
+    /// Loads a shader written in the given `language` from a file, blocking until it has
+    /// compiled. Shaders added from here will hot-reload.
+    pub fn load_file_as(
+        &mut self,
+        key: impl Nametag,
+        path: impl AsRef<Path>,
+        entry_point: impl Into<String>,
+        kind: shaderc::ShaderKind,
+        language: ShaderLanguage,
+        options: Option<&ShaderOptions>,
+    ) -> Result<(), Error> {
+        let path = std::fs::canonicalize(path)?;
+        let src_desc =
+            ShaderSourceDescriptor::file(path, entry_point.into(), kind, language, options);
+        self.queue(register_tag(key), src_desc).wait()
+    }
+
+    /// Loads a shader from an `&str` source string, blocking until it has compiled.
+    ///
+    /// The language is picked from the extension of `filename` with
+    /// [`ShaderLanguage::from_path`].
     pub fn load_src(
         &mut self,
         key: impl Nametag,
@@ -350,23 +1451,293 @@ impl ShaderState {
         src: &str,
         entry_point: &str,
         kind: shaderc::ShaderKind,
-        _options: Option<&shaderc::CompileOptions>,
-    ) {
-        let src_desc = ShaderSourceDescriptor {
-            path: None,
-            filename: Some(filename.to_owned()),
-            data: Some(src.to_owned()),
-            entry_point: entry_point.to_owned(),
-            kind,
+        options: Option<&ShaderOptions>,
+    ) -> Result<(), Error> {
+        self.load_src_async(key, filename, src, entry_point, kind, options)
+            .wait()
+    }
+
+    /// Loads a shader from an `&str` source string without waiting for it to compile.
+    ///
+    /// The language is picked from the extension of `filename` with
+    /// [`ShaderLanguage::from_path`].
+    pub fn load_src_async(
+        &mut self,
+        key: impl Nametag,
+        filename: &str,
+        src: &str,
+        entry_point: &str,
+        kind: shaderc::ShaderKind,
+        options: Option<&ShaderOptions>,
+    ) -> PendingShader {
+        let language = ShaderLanguage::from_path(filename);
+        let src_desc =
+            ShaderSourceDescriptor::src(filename, src, entry_point, kind, language, options);
+        self.queue(register_tag(key), src_desc)
+    }
+
+    /// Loads a shader written in the given `language` from an `&str` source string,
+    /// blocking until it has compiled.
+    #[allow(clippy::too_many_arguments)]
+    pub fn load_src_as(
+        &mut self,
+        key: impl Nametag,
+        filename: &str,
+        src: &str,
+        entry_point: &str,
+        kind: shaderc::ShaderKind,
+        language: ShaderLanguage,
+        options: Option<&ShaderOptions>,
+    ) -> Result<(), Error> {
+        let src_desc =
+            ShaderSourceDescriptor::src(filename, src, entry_point, kind, language, options);
+        self.queue(register_tag(key), src_desc).wait()
+    }
+
+    /// Compile every shader in the `batch` at once, spread across as many threads as there
+    /// are cores. This blocks until they have all compiled, and is mostly useful for loading
+    /// lots of shaders at startup.
+    ///
+    /// If any of the shaders fail, the errors from all of them are returned together.
+    pub fn load_batch(&mut self, batch: ShaderBatch) -> Result<(), Error> {
+        let mut pending = vec![];
+        let (job_tx, job_rx) = crossbeam_channel::unbounded();
+        for (tag, mut src_desc) in batch.shaders {
+            if let Some(path) = src_desc.path.take() {
+                let path = std::fs::canonicalize(path)?;
+                self.watch(&path)?;
+                src_desc.path = Some(path);
+            }
+            self.failures.remove(&tag);
+            let (done, rx) = oneshot::channel();
+            pending.push(PendingShader::new(rx));
+            job_tx.send((tag, src_desc, done)).unwrap();
+        }
+        drop(job_tx);
+
+        let threads = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .min(pending.len());
+        for _ in 0..threads {
+            let job_rx = job_rx.clone();
+            let tx = self.tx.clone();
+            let include_dirs = self.include_dirs.read().unwrap().clone();
+            let cache = self.cache.read().unwrap().clone();
+            let features = self.device.features();
+            std::thread::spawn(move || {
+                // If this fails, dropping the jobs tells whoever is waiting on them.
+                let mut compiler = match shaderc::Compiler::new() {
+                    Some(compiler) => compiler,
+                    None => return,
+                };
+                for (key, src_desc, done) in job_rx.iter() {
+                    let mut includes = vec![];
+                    let result = compile_source(
+                        &mut compiler,
+                        &src_desc,
+                        &include_dirs,
+                        &mut includes,
+                        cache.as_ref(),
+                        features,
+                    );
+                    // The rest of the work has to happen on the compiler thread, since
+                    // that's what owns the shaders and pipelines.
+                    let msg = CompilerMessage::Compiled {
+                        key,
+                        src_desc,
+                        result,
+                        includes,
+                        done: Some(done),
+                    };
+                    if tx.send(msg).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+
+        let mut errors = vec![];
+        for shader in pending {
+            match shader.wait() {
+                Ok(()) => (),
+                Err(Error::Shader(e)) => errors.extend(e),
+                Err(e) => return Err(e),
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(Error::Shader(errors)),
+        }
+    }
+
+    /// Send a shader off to the compiler thread.
+    fn queue(&mut self, tag: u128, src_desc: ShaderSourceDescriptor) -> PendingShader {
+        if let Some(path) = &src_desc.path {
+            if let Err(e) = self.watch(path) {
+                return PendingShader::failed(e);
+            }
+        }
+        self.failures.remove(&tag);
+        let (done, rx) = oneshot::channel();
+        let msg = match src_desc.path {
+            Some(_) => CompilerMessage::FromFile(tag, src_desc, Some(done)),
+            None => CompilerMessage::FromString(tag, src_desc, Some(done)),
         };
-        let tag = key.tag();
+        self.tx.send(msg).unwrap();
+        PendingShader::new(rx)
+    }
+
+    fn watch(&self, path: &Path) -> Result<(), Error> {
+        self.watches.lock().unwrap().watch_file(path)?;
+        Ok(())
+    }
+
+    /// Load every shader in `dir` and its subdirectories, and keep an eye on it from then on.
+    /// Files that change are reloaded, new files are loaded, and files that are removed are
+    /// unloaded, as in [`ShaderState::unload`].
+    ///
+    /// The stage is picked from the file extension (`.vert`, `.frag`, `.comp`, and so on),
+    /// and the language with [`ShaderLanguage::from_path`]. Files with any other extension
+    /// are skipped, so shared code can be kept in the same directory as `.glsl` files and
+    /// included. `naming` is handed the path of each file relative to `dir`, and gives back
+    /// the tag to load it as along with its entry point, or `None` to skip it. GLSL entry
+    /// points are always `main`, but WGSL files can name theirs anything, like `vs_main`.
+    ///
+    /// A shader failing to compile doesn't make this fail. Like a failed reload, it is
+    /// reported through [`ShaderState::errors`] and [`EngineEvent::ShaderFailed`] instead.
+    pub fn watch_dir(
+        &mut self,
+        dir: impl AsRef<Path>,
+        naming: impl Fn(&Path) -> Option<(Tag, String)> + Send + Sync + 'static,
+    ) -> Result<(), Error> {
+        let root = std::fs::canonicalize(dir)?;
+        self.watches.lock().unwrap().watch_tree(&root)?;
+        let dir = WatchedDir {
+            root,
+            naming: Box::new(naming),
+        };
+        let mut files = vec![];
+        walk_dir(&dir.root, &mut files)?;
+        let shaders = files.iter().filter_map(|path| dir.shader(path)).collect();
+        // Start listening for new files before loading the existing ones, so that nothing
+        // added in the meantime gets missed.
+        self.watched_dirs.write().unwrap().push(dir);
+        match self.load_batch(ShaderBatch { shaders }) {
+            Err(Error::Shader(_)) => Ok(()),
+            res => res,
+        }
+    }
+
+    /// Stop watching a directory added with [`ShaderState::watch_dir`]. New files in it are
+    /// no longer picked up, but the shaders already loaded from it stay loaded and keep
+    /// hot-reloading until they are unloaded.
+    pub fn unwatch_dir(&mut self, dir: impl AsRef<Path>) -> Result<(), Error> {
+        let root = std::fs::canonicalize(dir)?;
+        let mut watched_dirs = self.watched_dirs.write().unwrap();
+        watched_dirs.retain(|dir| dir.root != root);
+        let mut watches = self.watches.lock().unwrap();
+        watches.unwatch_tree(&root)?;
+        // Other watched directories might have been inside this one.
+        for dir in watched_dirs.iter() {
+            watches.watch_tree(&dir.root)?;
+        }
+        watches.retain_files(&watched_files(&self.compile_log, &self.include_map))?;
+        Ok(())
+    }
+
+    /// Unload the shader with the tag `key`, freeing its module and no longer watching its
+    /// files. Pipelines can only be rebuilt while their shaders are loaded, so a shader that
+    /// a pipeline still uses is kept around until every handle to the pipeline is dropped,
+    /// and is unloaded then. Loading the shader again in the meantime calls that off.
+    ///
+    /// Returns whether the shader was unloaded straight away. This waits for anything
+    /// already queued on the compiler thread first, so no [`ShaderRef`]s may be held while
+    /// calling it.
+    pub fn unload(&mut self, key: impl Nametag) -> bool {
+        let (done, rx) = oneshot::channel();
         self.tx
-            .send(CompilerMessage::FromString(tag, src_desc))
+            .send(CompilerMessage::Unload(key.tag(), Some(done)))
             .unwrap();
+        futures::executor::block_on(rx).unwrap_or(false)
+    }
 
-        while !self.shader_map.contains_key(&tag) {
-            continue;
-        }
+    /// Add a directory to search for `#include <...>` directives in. Quoted includes that
+    /// can't be found next to the file including them are searched for here too.
+    ///
+    /// Only shaders loaded after this is called will see the directory.
+    pub fn add_include_dir(&mut self, dir: impl AsRef<Path>) -> Result<(), Error> {
+        let dir = std::fs::canonicalize(dir)?;
+        self.include_dirs.write().unwrap().push(dir);
+        Ok(())
+    }
+
+    /// Keep compiled GLSL shaders in `dir`, creating it if needed, so that they can be loaded
+    /// from there instead of being compiled again on the next run. Entries are checked
+    /// against the shader source, its includes, its options and the `salt`, and are compiled
+    /// again if any of them have changed.
+    ///
+    /// There's no way to ask shaderc which version of glslang it was built with, and it may
+    /// be a system library that changes without the engine being rebuilt. The `salt` should
+    /// change whenever the compiler does, for example by being the version of the shaderc
+    /// library that's being linked against. Changing it is also a way to throw away every
+    /// entry in the cache.
+    ///
+    /// Only shaders loaded after this is called will use the cache.
+    pub fn set_cache_dir(
+        &mut self,
+        dir: impl AsRef<Path>,
+        salt: impl Into<String>,
+    ) -> Result<(), Error> {
+        *self.cache.write().unwrap() = Some(ShaderCache::new(dir, salt)?);
+        Ok(())
+    }
+
+    /// Every shader that failed its last compile, along with what went wrong. A shader that
+    /// fails to hot-reload keeps using the last version that compiled.
+    ///
+    /// Failures are also announced with [`EngineEvent::ShaderFailed`] as they happen.
+    pub fn errors(&self) -> Vec<(Tag, Vec<ShaderError>)> {
+        self.failures
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().clone()))
+            .collect()
+    }
+
+    /// Every shader that has been loaded, including ones that have only ever failed to
+    /// compile, in no particular order.
+    pub fn shaders(&self) -> Vec<ShaderInfo> {
+        self.compile_log
+            .iter()
+            .map(|entry| ShaderInfo {
+                tag: *entry.key(),
+                source: entry.source.clone(),
+                attempted_at: entry.attempted_at,
+                compiled_at: entry.compiled_at,
+                status: match self.failures.get(entry.key()) {
+                    Some(errors) => ShaderStatus::Failed(errors.clone()),
+                    None => ShaderStatus::Compiled,
+                },
+            })
+            .collect()
+    }
+
+    /// Every pipeline built through this state that still has a handle to it somewhere.
+    pub fn pipelines(&self) -> Vec<PipelineInfo> {
+        self.manufacturers
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|data| {
+                Some(PipelineInfo {
+                    label: data.label.clone(),
+                    shaders: data.tags,
+                    generation: data.pipeline.generation()?,
+                    surface_bound: data.swapchain_bound,
+                    error: data.error.clone(),
+                })
+            })
+            .collect()
     }
 
     /// Attempt to retrieve a shader with a given tag `key`.
@@ -375,41 +1746,68 @@ impl ShaderState {
     }
 
     /// Initialize the internal shaders for the program.
-    pub(crate) fn init_shaders(&mut self) {
-        self.load_src(
-            InternalShaders::IcedVert,
-            "iced.vert",
-            include_str!("gl/iced.vert"),
-            "main",
-            shaderc::ShaderKind::Vertex,
-            None,
-        );
-        self.load_src(
-            InternalShaders::IcedFrag,
-            "iced.frag",
-            include_str!("gl/iced.frag"),
-            "main",
-            shaderc::ShaderKind::Fragment,
-            None,
-        );
+    pub(crate) fn init_shaders(&mut self) -> Result<(), Error> {
+        let batch = ShaderBatch::new()
+            .src(
+                InternalShaders::IcedVert,
+                "iced.vert",
+                include_str!("gl/iced.vert"),
+                "main",
+                shaderc::ShaderKind::Vertex,
+                None,
+            )
+            .src(
+                InternalShaders::IcedFrag,
+                "iced.frag",
+                include_str!("gl/iced.frag"),
+                "main",
+                shaderc::ShaderKind::Fragment,
+                None,
+            );
+        self.load_batch(batch)
     }
-    /// Start constructing a new pipeline using the [`RenderPipelineBuilder`].
+
+    /// Start constructing a new pipeline using the [`RenderPipelineBuilder`]. Pass
+    /// [`PipelineLayoutSource::Auto`] as the `layout` to have it generated from the shaders.
     pub fn render_pipeline_builder<T: Into<String>>(
         &self,
         label: impl Into<Option<T>>,
-        layout: wgpu::PipelineLayout,
+        layout: impl Into<PipelineLayoutSource>,
         vertex: impl Nametag,
     ) -> RenderPipelineBuilder {
-        RenderPipelineBuilder::new(self, label.into().map(|i| i.into()), layout, vertex)
+        RenderPipelineBuilder::new(self, label.into().map(|i| i.into()), layout.into(), vertex)
     }
 
-    /// Create a new compute pipeline.
+    /// Generate the bind group layouts and pipeline layout for the shaders behind `keys`,
+    /// from the resources that they use. Layouts with the same entries are interchangeable,
+    /// so bind groups created with these also fit pipelines built with
+    /// [`PipelineLayoutSource::Auto`].
+    pub fn reflect_layout<T: Into<String>>(
+        &self,
+        label: impl Into<Option<T>>,
+        keys: impl IntoIterator<Item = impl Nametag>,
+    ) -> Result<ReflectedLayout, Error> {
+        let label: Option<String> = label.into().map(|i| i.into());
+        let shaders = keys
+            .into_iter()
+            .map(|key| loaded_shader(&self.shader_map, key.tag()))
+            .collect::<Result<Vec<ShaderRef>, Error>>()?;
+        let reflections = shaders
+            .iter()
+            .map(|shader| shader.reflection())
+            .collect::<Result<Vec<&ShaderReflection>, &str>>()
+            .map_err(|e| Error::Reflection(e.to_owned()))?;
+        crate::reflect::create_layout(&self.device, label.as_deref(), &reflections)
+            .map_err(Error::Reflection)
+    }
+
+    /// Create a new compute pipeline. This fails if `shader` isn't loaded.
     pub fn compute_pipeline<T: Into<String>>(
         &self,
         label: impl Into<Option<T>>,
         layout: impl Into<Option<wgpu::PipelineLayout>>,
         shader: impl Nametag,
-    ) -> Arc<ComputePipeline> {
+    ) -> Result<PipelineHandle<ComputePipeline>, Error> {
         ComputePipelineBuilder {
             state: self,
             label: label.into().map(|i| i.into()),
@@ -418,11 +1816,117 @@ impl ShaderState {
         }
         .build()
     }
+}
+
+impl Drop for ShaderState {
+    fn drop(&mut self) {
+        // Let the compiler thread finish what it's doing, so it isn't cut off halfway through
+        // building something on the device.
+        let _ = self.tx.send(CompilerMessage::Interrupt);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// A shader that has been sent off to be compiled. Either `.await` it, or block until it's
+/// done with [`PendingShader::wait`].
+#[must_use = "the shader may not have compiled yet"]
+pub struct PendingShader {
+    rx: Option<oneshot::Receiver<Result<(), Vec<ShaderError>>>>,
+    error: Option<Error>,
+}
+
+impl PendingShader {
+    fn new(rx: oneshot::Receiver<Result<(), Vec<ShaderError>>>) -> Self {
+        Self {
+            rx: Some(rx),
+            error: None,
+        }
+    }
+
+    fn failed(error: Error) -> Self {
+        Self {
+            rx: None,
+            error: Some(error),
+        }
+    }
+
+    /// Block until the shader has either compiled or failed to.
+    pub fn wait(self) -> Result<(), Error> {
+        futures::executor::block_on(self)
+    }
+}
+
+impl Future for PendingShader {
+    type Output = Result<(), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(error) = self.error.take() {
+            return Poll::Ready(Err(error));
+        }
+        let rx = self
+            .rx
+            .as_mut()
+            .expect("PendingShader polled after completion");
+        Pin::new(rx).poll(cx).map(|res| match res {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(errors)) => Err(Error::Shader(errors)),
+            // The compiler went away without getting to the shader.
+            Err(oneshot::Canceled) => Err(Error::ShaderCompiler),
+        })
+    }
+}
+
+/// A set of shaders to compile all at once with [`ShaderState::load_batch`]. The arguments
+/// to each method match the ones to [`ShaderState::load_file`] and [`ShaderState::load_src`].
+#[derive(Default)]
+pub struct ShaderBatch {
+    shaders: Vec<(u128, ShaderSourceDescriptor)>,
+}
+
+impl ShaderBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-    /// Tell the manufactory to cull all unused render pipelines.
-    #[inline(always)]
-    pub(crate) fn cull(&mut self) {
-        self.tx.send(CompilerMessage::CullRefs).unwrap();
+    /// Add a shader from a file. Shaders added from here will hot-reload.
+    pub fn file(
+        mut self,
+        key: impl Nametag,
+        path: impl AsRef<Path>,
+        entry_point: impl Into<String>,
+        kind: shaderc::ShaderKind,
+        options: Option<&ShaderOptions>,
+    ) -> Self {
+        let path = path.as_ref();
+        let language = ShaderLanguage::from_path(path);
+        let src_desc = ShaderSourceDescriptor::file(
+            path.to_owned(),
+            entry_point.into(),
+            kind,
+            language,
+            options,
+        );
+        self.shaders.push((register_tag(key), src_desc));
+        self
+    }
+
+    /// Add a shader from an `&str` source string.
+    pub fn src(
+        mut self,
+        key: impl Nametag,
+        filename: &str,
+        src: &str,
+        entry_point: &str,
+        kind: shaderc::ShaderKind,
+        options: Option<&ShaderOptions>,
+    ) -> Self {
+        let language = ShaderLanguage::from_path(filename);
+        let src_desc =
+            ShaderSourceDescriptor::src(filename, src, entry_point, kind, language, options);
+        self.shaders.push((register_tag(key), src_desc));
+        self
     }
 }
 
@@ -433,7 +1937,8 @@ pub enum InternalShaders {
     IcedFrag,
 }
 
-#[derive(Hash, Copy, Clone)]
+/// The shaders that a pipeline is built from.
+#[derive(Hash, Copy, Clone, Debug, PartialEq, Eq)]
 pub struct ShaderTags {
     pub vertex: Option<u128>,
     pub fragment: Option<u128>,
@@ -459,8 +1964,18 @@ impl ShaderTags {
 }
 
 impl ShaderTags {
+    /// A label made from the names of the shaders, for pipelines that weren't given one.
+    fn label(&self) -> String {
+        [self.vertex, self.fragment, self.compute]
+            .iter()
+            .flatten()
+            .map(|tag| TagName(*tag).to_string())
+            .collect::<Vec<_>>()
+            .join(" + ")
+    }
+
     fn has_tag(&self, tag: u128) -> bool {
-        [self.vertex, self.fragment]
+        [self.vertex, self.fragment, self.compute]
             .iter()
             .map(|i| i.map(|j| j == tag).unwrap_or(false))
             .fold(false, |a, i| a | i)
@@ -473,14 +1988,47 @@ pub struct ShaderSet<'a> {
     pub compute: Option<ShaderRef<'a>>,
 }
 
+/// Where a render pipeline gets its layout from.
+pub enum PipelineLayoutSource {
+    /// A layout made by hand, which has to match the bindings in the shaders.
+    Explicit(PipelineLayout),
+    /// Generate the layout from the resources the pipeline's shaders use. It's generated
+    /// again whenever the shaders are reloaded. Bind groups for the pipeline can be created
+    /// from the layouts given by [`ShaderState::reflect_layout`].
+    Auto,
+}
+
+impl From<PipelineLayout> for PipelineLayoutSource {
+    fn from(layout: PipelineLayout) -> Self {
+        PipelineLayoutSource::Explicit(layout)
+    }
+}
+
+/// Generate a layout for the shaders in `set`, failing if any of them can't be reflected or
+/// they disagree about a binding.
+fn auto_layout(
+    device: &wgpu::Device,
+    label: Option<&str>,
+    set: &ShaderSet,
+) -> Result<PipelineLayout, String> {
+    let reflections = [&set.vertex, &set.fragment, &set.compute]
+        .into_iter()
+        .flatten()
+        .map(|shader| shader.reflection())
+        .collect::<Result<Vec<&ShaderReflection>, &str>>()?;
+    crate::reflect::create_layout(device, label, &reflections).map(|l| l.pipeline_layout)
+}
+
 /// This tells the engine how to build your render pipelines.
 /// Created from [`ShaderState::render_pipeline_builder`].
 pub struct RenderPipelineBuilder<'a> {
     state: &'a ShaderState,
     label: Option<String>,
-    layout: wgpu::PipelineLayout,
+    layout: PipelineLayoutSource,
     vertex: u128,
+    vertex_buffers: Vec<VertexLayout>,
     fragment: Option<(u128, Vec<wgpu::ColorTargetState>)>,
+    surface_targets: Vec<usize>,
     primitive: wgpu::PrimitiveState,
     depth_stencil: Option<wgpu::DepthStencilState>,
     multisample: wgpu::MultisampleState,
@@ -491,7 +2039,7 @@ impl<'a> RenderPipelineBuilder<'a> {
     pub(crate) fn new(
         state: &'a ShaderState,
         label: Option<String>,
-        layout: wgpu::PipelineLayout,
+        layout: PipelineLayoutSource,
         vertex: impl Nametag,
     ) -> Self {
         Self {
@@ -499,7 +2047,9 @@ impl<'a> RenderPipelineBuilder<'a> {
             label,
             layout,
             vertex: vertex.tag(),
+            vertex_buffers: vec![],
             fragment: None,
+            surface_targets: vec![],
             primitive: wgpu::PrimitiveState {
                 topology: wgpu::PrimitiveTopology::TriangleList,
                 strip_index_format: None,
@@ -519,7 +2069,16 @@ impl<'a> RenderPipelineBuilder<'a> {
         }
     }
 
+    /// Set the layouts of the vertex buffers the vertex shader reads from. Use
+    /// [`Vertex::layout`](crate::Vertex::layout) to get one for a vertex type.
+    pub fn vertex_buffers(mut self, vertex_buffers: impl ToVec<VertexLayout>) -> Self {
+        self.vertex_buffers = vertex_buffers.to_vec();
+        self
+    }
+
     /// Set which fragment shader to use, and what [`wgpu::ColorTargetState`]s to target.
+    /// Targets that draw to the surface should be marked with
+    /// [`RenderPipelineBuilder::surface_target`].
     pub fn fragment(
         mut self,
         fragment: impl Nametag,
@@ -529,6 +2088,13 @@ impl<'a> RenderPipelineBuilder<'a> {
         self
     }
 
+    /// Mark the fragment target at `index` as drawing to the surface. Its format is replaced
+    /// with the surface's, and the pipeline is rebuilt whenever the surface format changes.
+    pub fn surface_target(mut self, index: usize) -> Self {
+        self.surface_targets.push(index);
+        self
+    }
+
     /// Set the primitive state.
     pub fn primitive(mut self, primitive: wgpu::PrimitiveState) -> Self {
         self.primitive = primitive;
@@ -560,8 +2126,14 @@ impl<'a> RenderPipelineBuilder<'a> {
         self
     }
 
-    /// Add the info to the render pipeline manufactory and immediately give back a [`wgpu::RenderPipeline`].
-    pub fn build(self) -> Arc<RenderPipeline> {
+    /// Add the info to the render pipeline manufactory and immediately give back a handle to
+    /// the [`wgpu::RenderPipeline`], which stays up to date as its shaders are reloaded.
+    ///
+    /// This fails if any of the shaders aren't loaded, or if the layout is
+    /// [`PipelineLayoutSource::Auto`] and it can't be generated from the shaders. If the
+    /// latter happens on a later reload, the old pipeline is kept and the
+    /// error shows up in [`ShaderState::pipelines`].
+    pub fn build(self) -> Result<PipelineHandle<RenderPipeline>, Error> {
         let lbl = self.label.clone();
         let state = self.state;
         let multisample = self.multisample;
@@ -569,36 +2141,81 @@ impl<'a> RenderPipelineBuilder<'a> {
         let multiview = self.multiview;
         let depth_stencil = self.depth_stencil;
         let layout = self.layout;
-        let vert_ref = state.shader_map.get(&self.vertex).unwrap();
-        let vert_main = vert_ref.0.as_ref().unwrap().entry_point.clone();
-        let vertex = Some(ShaderRef(vert_ref));
+        let vertex_buffers = self.vertex_buffers;
+        // Hold on to the manufacturers while building, so a format change can't slip in
+        // between reading the format and registering the pipeline to follow it.
+        let mut mfs = state.manufacturers.write().unwrap();
+        let vert_ref = loaded_shader(&state.shader_map, self.vertex)?;
+        let vert_main = vert_ref.0 .0.as_ref().unwrap().entry_point.clone();
+        let vertex = Some(vert_ref);
         let frag_tag = self.fragment.as_ref().map(|i| i.0);
         let vert_tag = self.vertex;
-        let fragment = self.fragment.map(|i| {
-            let frag_ref = state.shader_map.get(&i.0).unwrap();
-            (
-                frag_ref.0.as_ref().unwrap().entry_point.clone(),
-                ShaderRef(frag_ref),
-                i.1,
-            )
-        });
+        let fragment = match self.fragment {
+            Some((tag, targets)) => {
+                let frag_ref = loaded_shader(&state.shader_map, tag)?;
+                let main = frag_ref.0 .0.as_ref().unwrap().entry_point.clone();
+                Some((main, frag_ref, targets))
+            }
+            None => None,
+        };
         let (fragment, frag_data) = match fragment {
             Some((tag, r, targets)) => (Some(r), Some((tag, targets))),
             None => (None, None),
         };
         let tags = ShaderTags::render(vert_tag, frag_tag);
-        let manufacturer = Box::new(move |dev: &wgpu::Device, shaders: ShaderSet| {
+        let lbl = lbl.or_else(|| Some(tags.label()));
+        let surface_format = *state.surface_format.read().unwrap();
+        let target_count = frag_data.as_ref().map_or(0, |(_, targets)| targets.len());
+        if let Some(&index) = self.surface_targets.iter().find(|&&i| i >= target_count) {
+            panic!(
+                "Surface target {} is out of range for pipeline {}, which has {} targets",
+                index,
+                tags.label(),
+                target_count
+            );
+        }
+        let swapchain_targets: Vec<bool> = (0..target_count)
+            .map(|i| self.surface_targets.contains(&i))
+            .collect();
+        let swapchain_bound = swapchain_targets.contains(&true);
+        let manufacturer = Box::new(move |dev: &wgpu::Device, shaders: ShaderSet, format| {
             let label: Option<&str> = lbl.as_ref().map(|i| i.as_str());
-            dev.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            let targets: Vec<wgpu::ColorTargetState> = frag_data
+                .as_ref()
+                .map(|(_, targets)| {
+                    targets
+                        .iter()
+                        .zip(swapchain_targets.iter())
+                        .map(|(target, &bound)| match bound {
+                            true => wgpu::ColorTargetState {
+                                format,
+                                ..target.clone()
+                            },
+                            false => target.clone(),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            let buffers: Vec<wgpu::VertexBufferLayout> =
+                vertex_buffers.iter().map(|b| b.as_wgpu()).collect();
+            let generated;
+            let layout = match &layout {
+                PipelineLayoutSource::Explicit(layout) => layout,
+                PipelineLayoutSource::Auto => {
+                    generated = auto_layout(dev, label, &shaders)?;
+                    &generated
+                }
+            };
+            let pipeline = dev.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
                 label,
-                layout: Some(&layout),
+                layout: Some(layout),
                 vertex: wgpu::VertexState {
                     module: &shaders.vertex.unwrap(),
                     entry_point: vert_main.as_str(),
-                    buffers: &[],
+                    buffers: &buffers,
                 },
                 fragment: shaders.fragment.as_ref().map(|frag| {
-                    let (main, targets) = frag_data.as_ref().unwrap();
+                    let (main, _) = frag_data.as_ref().unwrap();
                     wgpu::FragmentState {
                         module: frag,
                         entry_point: main,
@@ -609,10 +2226,10 @@ impl<'a> RenderPipelineBuilder<'a> {
                 depth_stencil: depth_stencil.clone(),
                 multisample,
                 multiview,
-            })
-            .into()
+            });
+            Ok(pipeline.into())
         }) as Manufacturer;
-        let ret = Arc::new(
+        let ret = PipelineHandle::new(
             (manufacturer)(
                 &state.device,
                 ShaderSet {
@@ -620,16 +2237,22 @@ impl<'a> RenderPipelineBuilder<'a> {
                     fragment,
                     compute: None,
                 },
+                surface_format,
             )
+            .map_err(Error::Reflection)?
             .render(),
+            state.tx.clone(),
         );
         let val = ManufacturingData::new(
             manufacturer,
+            self.label,
             tags,
-            Arc::downgrade(&ret) as Weak<dyn Send + Sync>,
+            Box::new(Arc::downgrade(&ret.shared)),
+            swapchain_bound,
         );
-        state.manufacturers.write().unwrap().push(val);
-        ret
+        mfs.retain(|data| data.pipeline.is_alive());
+        mfs.push(val);
+        Ok(ret)
     }
 }
 
@@ -641,36 +2264,46 @@ pub struct ComputePipelineBuilder<'a> {
 }
 
 impl<'a> ComputePipelineBuilder<'a> {
-    fn build(self) -> Arc<ComputePipeline> {
+    fn build(self) -> Result<PipelineHandle<ComputePipeline>, Error> {
         let state = self.state;
-        let label = self.label;
+        let pipeline_label = self.label.clone();
+        let label = self
+            .label
+            .or_else(|| Some(ShaderTags::compute(self.module).label()));
         let layout = self.layout;
         let module = self.module;
-        let comp_ref = state.shader_map.get(&self.module).unwrap();
-        let entry_point = comp_ref.0.as_ref().unwrap().entry_point.clone();
+        let comp_ref = loaded_shader(&state.shader_map, self.module)?;
+        let entry_point = comp_ref.0 .0.as_ref().unwrap().entry_point.clone();
         let set = ShaderSet {
             vertex: None,
             fragment: None,
-            compute: Some(ShaderRef(comp_ref)),
+            compute: Some(comp_ref),
         };
-        let manufacturer = Box::new(move |dev: &wgpu::Device, shaders: ShaderSet| {
-            dev.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        let manufacturer = Box::new(move |dev: &wgpu::Device, shaders: ShaderSet, _| {
+            let pipeline = dev.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
                 label: label.as_deref(),
                 layout: layout.as_ref(),
                 module: &shaders.compute.unwrap(),
                 entry_point: entry_point.as_str(),
-            })
-            .into()
+            });
+            Ok(pipeline.into())
         }) as Manufacturer;
         let tags = ShaderTags::compute(module);
-        let ret = Arc::new((manufacturer)(&state.device, set).compute());
+        let surface_format = *state.surface_format.read().unwrap();
+        let pipeline = (manufacturer)(&state.device, set, surface_format)
+            .expect("Compute pipelines don't generate their layout");
+        let ret = PipelineHandle::new(pipeline.compute(), state.tx.clone());
         let val = ManufacturingData::new(
             manufacturer,
+            pipeline_label,
             tags,
-            Arc::downgrade(&ret) as Weak<dyn Send + Sync>,
+            Box::new(Arc::downgrade(&ret.shared)),
+            false,
         );
-        state.manufacturers.write().unwrap().push(val);
-        ret
+        let mut mfs = state.manufacturers.write().unwrap();
+        mfs.retain(|data| data.pipeline.is_alive());
+        mfs.push(val);
+        Ok(ret)
     }
 }
 
@@ -695,3 +2328,59 @@ impl<T, const N: usize> ToVec<T> for [T; N] {
         self.into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_shaderc_line() {
+        let error = ShaderError::parse_shaderc_line(
+            "shader.frag",
+            "shader.frag:12: error: 'foo' : undeclared identifier",
+        );
+        assert_eq!(
+            error,
+            ShaderError {
+                file: "shader.frag".to_owned(),
+                line: Some(12),
+                column: None,
+                message: "'foo' : undeclared identifier".to_owned(),
+            }
+        );
+    }
+
+    #[test]
+    fn parses_shaderc_line_with_drive_letter() {
+        let error = ShaderError::parse_shaderc_line(
+            "shader.frag",
+            r"C:\shaders\common.glsl:3: error: '' : syntax error",
+        );
+        assert_eq!(error.file, r"C:\shaders\common.glsl");
+        assert_eq!(error.line, Some(3));
+        assert_eq!(error.message, "'' : syntax error");
+    }
+
+    #[test]
+    fn keeps_unparseable_shaderc_line() {
+        let error = ShaderError::parse_shaderc_line("shader.frag", " something went wrong ");
+        assert_eq!(
+            error,
+            ShaderError::new("shader.frag", "something went wrong")
+        );
+    }
+
+    #[test]
+    fn skips_shaderc_summary() {
+        let output = "shader.vert:1: error: 'a' : undeclared identifier\n\
+                      shader.vert:2: error: 'b' : undeclared identifier\n\
+                      2 errors generated.\n";
+        let errors = ShaderError::from_shaderc(
+            "shader.vert",
+            shaderc::Error::CompilationError(2, output.to_owned()),
+        );
+        let lines: Vec<Option<u32>> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, [Some(1), Some(2)]);
+        assert!(errors.iter().all(|e| !e.message.contains("generated")));
+    }
+}
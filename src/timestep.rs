@@ -0,0 +1,124 @@
+use std::time::{Duration, Instant};
+
+/// Drives the fixed-rate simulation loop by accumulating real time and handing it out
+/// in evenly sized steps.
+pub struct FixedTimestep {
+    step: Duration,
+    max_steps: u32,
+    accumulator: Duration,
+    last_time: Instant,
+    alpha: f32,
+}
+
+impl FixedTimestep {
+    pub(crate) fn new(tick_rate: f32, max_steps: u32) -> Self {
+        Self {
+            step: Duration::from_secs_f32(1.0 / tick_rate),
+            max_steps,
+            accumulator: Duration::ZERO,
+            last_time: Instant::now(),
+            alpha: 0.0,
+        }
+    }
+
+    /// Restart the clock, throwing away any time that has built up.
+    pub(crate) fn reset(&mut self) {
+        self.accumulator = Duration::ZERO;
+        self.last_time = Instant::now();
+        self.alpha = 0.0;
+    }
+
+    /// Add the time elapsed since the last call to the accumulator, and return how many
+    /// steps should be simulated to catch up. If the simulation falls more than
+    /// `max_steps` behind, the rest of the backlog is dropped.
+    pub(crate) fn advance(&mut self) -> u32 {
+        let now = Instant::now();
+        let elapsed = now - self.last_time;
+        self.last_time = now;
+        self.advance_by(elapsed)
+    }
+
+    /// Advance by exactly one step, regardless of how much real time has passed.
+    pub(crate) fn advance_fixed(&mut self) -> u32 {
+        self.advance_by(self.step)
+    }
+
+    fn advance_by(&mut self, elapsed: Duration) -> u32 {
+        self.accumulator += elapsed;
+        let mut steps = 0;
+        while self.accumulator >= self.step && steps < self.max_steps {
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+        if self.accumulator >= self.step {
+            let excess = self.accumulator.as_nanos() % self.step.as_nanos();
+            self.accumulator = Duration::from_nanos(excess as u64);
+        }
+
+        // The accumulator is always less than a step here, but the division can still round up
+        // to 1.0 when it's within a few nanoseconds, so clamp to the largest f32 below 1.
+        let alpha = self.accumulator.as_secs_f32() / self.step.as_secs_f32();
+        self.alpha = alpha.min(1.0 - f32::EPSILON / 2.0);
+        steps
+    }
+
+    /// The length of a single simulation step, in seconds.
+    #[inline(always)]
+    pub fn dt(&self) -> f32 {
+        self.step.as_secs_f32()
+    }
+
+    /// How many simulation steps are run per second.
+    #[inline(always)]
+    pub fn tick_rate(&self) -> f32 {
+        1.0 / self.step.as_secs_f32()
+    }
+
+    /// How far the current frame is between the last simulation step and the next one,
+    /// from `0.0` to `1.0`. Use this to interpolate between simulation states when rendering.
+    #[inline(always)]
+    pub fn alpha(&self) -> f32 {
+        self.alpha
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_elapsed_runs_several_steps() {
+        let mut timestep = FixedTimestep::new(64.0, 5);
+        let step = timestep.step;
+        assert_eq!(timestep.advance_by(step * 3 + step / 2), 3);
+        assert!((timestep.alpha() - 0.5).abs() < 1e-4);
+        assert_eq!(timestep.advance_by(step / 2), 1);
+        assert!(timestep.alpha() < 1e-4);
+    }
+
+    #[test]
+    fn excess_past_max_catch_up_is_dropped() {
+        let mut timestep = FixedTimestep::new(64.0, 5);
+        let step = timestep.step;
+        assert_eq!(timestep.advance_by(step * 20 + step / 4), 5);
+        assert!(timestep.accumulator < step);
+        assert!((timestep.alpha() - 0.25).abs() < 1e-4);
+        assert_eq!(timestep.advance_by(Duration::ZERO), 0);
+    }
+
+    #[test]
+    fn alpha_stays_below_one() {
+        let mut timestep = FixedTimestep::new(60.0, 3);
+        let step = timestep.step;
+        for elapsed in [
+            step - Duration::from_nanos(1),
+            step,
+            step * 7,
+            step * 100 + step / 3,
+        ] {
+            timestep.advance_by(elapsed);
+            let alpha = timestep.alpha();
+            assert!((0.0..1.0).contains(&alpha), "alpha {} out of range", alpha);
+        }
+    }
+}
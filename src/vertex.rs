@@ -0,0 +1,100 @@
+use crate::wgpu;
+
+/// An owned version of [`wgpu::VertexBufferLayout`], so that it can be kept around to
+/// rebuild pipelines with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VertexLayout {
+    pub array_stride: wgpu::BufferAddress,
+    pub step_mode: wgpu::VertexStepMode,
+    pub attributes: Vec<wgpu::VertexAttribute>,
+}
+
+impl VertexLayout {
+    /// Describe a buffer where each element is `array_stride` bytes long, and is read once
+    /// per vertex.
+    pub fn new(
+        array_stride: wgpu::BufferAddress,
+        attributes: impl Into<Vec<wgpu::VertexAttribute>>,
+    ) -> Self {
+        Self {
+            array_stride,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: attributes.into(),
+        }
+    }
+
+    /// Set how often the buffer is advanced, e.g. once per instance instead of once per vertex.
+    pub fn step_mode(mut self, step_mode: wgpu::VertexStepMode) -> Self {
+        self.step_mode = step_mode;
+        self
+    }
+
+    /// Borrow this as a [`wgpu::VertexBufferLayout`].
+    pub fn as_wgpu(&self) -> wgpu::VertexBufferLayout<'_> {
+        wgpu::VertexBufferLayout {
+            array_stride: self.array_stride,
+            step_mode: self.step_mode,
+            attributes: &self.attributes,
+        }
+    }
+}
+
+impl<'a> From<wgpu::VertexBufferLayout<'a>> for VertexLayout {
+    fn from(layout: wgpu::VertexBufferLayout<'a>) -> Self {
+        Self {
+            array_stride: layout.array_stride,
+            step_mode: layout.step_mode,
+            attributes: layout.attributes.to_vec(),
+        }
+    }
+}
+
+/// A type that can be put in a vertex buffer. Use `#[derive(Vertex)]` on a `#[repr(C)]`
+/// struct to implement this, which gives each field the next shader location in order.
+/// A field's location and format can be overridden with
+/// `#[vertex(location = 3, format = "Unorm8x4")]`.
+pub trait Vertex {
+    /// The layout of a buffer full of this type.
+    fn layout() -> VertexLayout;
+}
+
+/// Types that can be used as a field in a [`Vertex`], and the format they take up in the
+/// vertex buffer.
+pub trait VertexAttributeFormat {
+    const FORMAT: wgpu::VertexFormat;
+}
+
+macro_rules! attribute_formats {
+    ($($ty:ty => $format:ident),* $(,)?) => {
+        $(impl VertexAttributeFormat for $ty {
+            const FORMAT: wgpu::VertexFormat = wgpu::VertexFormat::$format;
+        })*
+    };
+}
+
+attribute_formats! {
+    f32 => Float32,
+    [f32; 2] => Float32x2,
+    [f32; 3] => Float32x3,
+    [f32; 4] => Float32x4,
+    f64 => Float64,
+    [f64; 2] => Float64x2,
+    [f64; 3] => Float64x3,
+    [f64; 4] => Float64x4,
+    u32 => Uint32,
+    [u32; 2] => Uint32x2,
+    [u32; 3] => Uint32x3,
+    [u32; 4] => Uint32x4,
+    i32 => Sint32,
+    [i32; 2] => Sint32x2,
+    [i32; 3] => Sint32x3,
+    [i32; 4] => Sint32x4,
+    [u16; 2] => Uint16x2,
+    [u16; 4] => Uint16x4,
+    [i16; 2] => Sint16x2,
+    [i16; 4] => Sint16x4,
+    [u8; 2] => Uint8x2,
+    [u8; 4] => Uint8x4,
+    [i8; 2] => Sint8x2,
+    [i8; 4] => Sint8x4,
+}